@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use common::coordinates::{world_to_chunk_position, world_to_column_position};
+use common::packets::{
+    ChunkRequest, ChunkUpdate, ChunkUpdateAck, ChunkUpdateOutcome, ChunkUpdateType, Packet,
+    PlayerConnect, PlayerInfoData, PlayerInfoRequest, CAPABILITY_LZ4, PROTOCOL_VERSION,
+    SUPPORTED_CAPABILITIES,
+};
+
+use crate::stats::Stats;
+
+/// How often a bot reports its position, the same as the real client
+const MOVE_INTERVAL: Duration = Duration::from_millis(500);
+/// How far above a bot the blocks it places and breaks are, close enough to be within reach
+const EDIT_OFFSET: i32 = 2;
+/// Block bots place
+const EDIT_BLOCK_ID: i32 = 1;
+/// Columns around its own that a bot requests, in turn
+const REQUEST_OFFSETS: [(i32, i32); 9] = [
+    (0, 0),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// A loop walked at a constant speed
+#[derive(Clone, Debug)]
+pub struct ScriptedPath {
+    corners: Vec<Vector3<f32>>,
+    /// Blocks walked each second
+    speed: f32,
+}
+
+impl ScriptedPath {
+    /// A square `2 * half_size` blocks wide around `center`
+    pub fn square(center: Vector3<f32>, half_size: f32, speed: f32) -> ScriptedPath {
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|(x, z)| center + Vector3::new(x * half_size, 0.0, z * half_size))
+            .collect();
+        ScriptedPath { corners, speed }
+    }
+
+    /// Blocks walked each second
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Where a bot that started walking `elapsed` ago is
+    pub fn position_at(&self, elapsed: Duration) -> Vector3<f32> {
+        let segments: Vec<(Vector3<f32>, Vector3<f32>)> = (0..self.corners.len())
+            .map(|i| {
+                let next = (i + 1) % self.corners.len();
+                (self.corners[i], self.corners[next])
+            })
+            .collect();
+        let length: f32 = segments.iter().map(|(a, b)| (b - a).magnitude()).sum();
+        if length <= 0.0 {
+            return self.corners[0];
+        }
+
+        let mut distance = (elapsed.as_secs_f32() * self.speed) % length;
+        for (start, end) in segments {
+            let segment = (end - start).magnitude();
+            if distance <= segment {
+                return start + (end - start) * (distance / segment);
+            }
+            distance -= segment;
+        }
+        self.corners[0]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BotState {
+    /// Waiting for the transport to open the connection
+    Connecting,
+    /// Waiting for the server to accept the login
+    LoggingIn,
+    Playing,
+    Closed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Movement {
+    /// Waiting for the server to say where the bot is
+    Unplaced {
+        requested: bool,
+    },
+    /// Heading in a straight line from where the server placed it to the start of its path
+    Travelling {
+        from: Vector3<f32>,
+        started: Instant,
+    },
+    Walking {
+        started: Instant,
+    },
+}
+
+/// A headless player that walks a path, requests chunks and places and breaks blocks
+pub struct Bot {
+    pub username: String,
+    pub state: BotState,
+    path: ScriptedPath,
+    /// Time between chunk requests and block edits, which alternate
+    action_interval: Duration,
+    compress: bool,
+    created: Instant,
+    movement: Movement,
+    last_move: Option<Instant>,
+    last_action: Option<Instant>,
+    actions: usize,
+    loaded_chunks: HashSet<Vector3<i32>>,
+    /// Blocks this bot placed, which it breaks the next time it is above them
+    placed: HashSet<Vector3<i32>>,
+    chunk_requests: VecDeque<(Vector2<i32>, Instant)>,
+    /// Block edits waiting for an ack, by sequence number
+    block_updates: HashMap<u32, (Vector3<i32>, Instant)>,
+    next_sequence: u32,
+}
+
+impl Bot {
+    pub fn new(username: &str, path: ScriptedPath, actions_per_second: f32) -> Bot {
+        Bot {
+            username: username.to_string(),
+            state: BotState::Connecting,
+            path,
+            action_interval: Duration::from_secs_f32(1.0 / actions_per_second.max(0.001)),
+            compress: false,
+            created: Instant::now(),
+            movement: Movement::Unplaced { requested: false },
+            last_move: None,
+            last_action: None,
+            actions: 0,
+            loaded_chunks: HashSet::new(),
+            placed: HashSet::new(),
+            chunk_requests: VecDeque::new(),
+            block_updates: HashMap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    /// Whether large packets to the server should be compressed
+    pub fn compresses(&self) -> bool {
+        self.compress
+    }
+
+    /// Logs in once the connection is open
+    pub fn connected(&mut self) -> Packet {
+        self.state = BotState::LoggingIn;
+        Packet::PlayerConnect(PlayerConnect {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: SUPPORTED_CAPABILITIES,
+            username: self.username.clone(),
+        })
+    }
+
+    /// Reacts to a packet from the server, returning whether the bot should disconnect
+    pub fn handle_packet(&mut self, packet: Packet, now: Instant, stats: &mut Stats) -> bool {
+        match packet {
+            Packet::ServerInfo(info) => {
+                self.compress = info.capabilities & CAPABILITY_LZ4 != 0;
+                self.state = BotState::Playing;
+                stats.logins += 1;
+                stats.login_latency.record(now - self.created);
+            }
+            Packet::AuthChallenge(_) => {
+                eprintln!(
+                    "{} has an account on the server, bots cannot log in as it",
+                    self.username
+                );
+                return true;
+            }
+            // Where the bot was saved, or where the server put it back to after a rejected move
+            Packet::PlayerInfoData(info) if info.username == self.username => {
+                self.movement = Movement::Travelling {
+                    from: info.position,
+                    started: now,
+                };
+            }
+            Packet::ChunkContents(contents) => {
+                // Requests only ask for the lowest chunk, streamed columns hold every chunk
+                if let [chunk] = contents.chunks.as_slice() {
+                    let column = Vector2::new(chunk.position.x, chunk.position.z);
+                    let index = self
+                        .chunk_requests
+                        .iter()
+                        .position(|(requested, _)| *requested == column);
+                    if let Some((_, sent)) = index.and_then(|i| self.chunk_requests.remove(i)) {
+                        stats.chunk_latency.record(now - sent);
+                    }
+                }
+                self.loaded_chunks
+                    .extend(contents.chunks.iter().map(|chunk| chunk.position));
+            }
+            Packet::UnloadColumns(unload) => {
+                let columns: HashSet<Vector2<i32>> = unload.column_positions.into_iter().collect();
+                self.loaded_chunks
+                    .retain(|chunk| !columns.contains(&Vector2::new(chunk.x, chunk.z)));
+            }
+            Packet::ChunkUpdateAck(ack) => self.acknowledged(ack, now, stats),
+            Packet::Disconnect(disconnect) => {
+                eprintln!("{} was disconnected: {}", self.username, disconnect.reason);
+                self.state = BotState::Closed;
+            }
+            _ => (),
+        }
+        false
+    }
+
+    fn acknowledged(&mut self, ack: ChunkUpdateAck, now: Instant, stats: &mut Stats) {
+        let (position, sent) = match self.block_updates.remove(&ack.sequence) {
+            Some(update) => update,
+            None => return,
+        };
+        stats.block_latency.record(now - sent);
+        if let ChunkUpdateOutcome::Rejected { .. } = ack.outcome {
+            // A rejected placement leaves nothing to break later
+            self.placed.remove(&position);
+            stats.rejected_edits += 1;
+        }
+    }
+
+    /// Where the bot is at `now`, once the server has placed it
+    fn position_at(&mut self, now: Instant) -> Option<Vector3<f32>> {
+        match self.movement {
+            Movement::Unplaced { .. } => None,
+            Movement::Travelling { from, started } => {
+                let start = self.path.position_at(Duration::ZERO);
+                let travelled = (now - started).as_secs_f32() * self.path.speed();
+                let distance = (start - from).magnitude();
+                if travelled < distance {
+                    return Some(from + (start - from) * (travelled / distance));
+                }
+                let travel_time = distance / self.path.speed().max(f32::EPSILON);
+                let arrived = started + Duration::from_secs_f32(travel_time);
+                self.movement = Movement::Walking { started: arrived };
+                self.position_at(now)
+            }
+            Movement::Walking { started } => Some(self.path.position_at(now - started)),
+        }
+    }
+
+    /// Returns the packets the bot wants to send by `now`
+    pub fn update(&mut self, now: Instant) -> Vec<Packet> {
+        if self.state != BotState::Playing {
+            return Vec::new();
+        }
+        if let Movement::Unplaced { requested: false } = self.movement {
+            self.movement = Movement::Unplaced { requested: true };
+            return vec![Packet::PlayerInfoRequest(PlayerInfoRequest {})];
+        }
+        let position = match self.position_at(now) {
+            Some(position) => position,
+            None => return Vec::new(),
+        };
+        let mut packets = Vec::new();
+
+        if is_due(self.last_move, MOVE_INTERVAL, now) {
+            self.last_move = Some(now);
+            packets.push(Packet::PlayerInfoData(PlayerInfoData {
+                username: self.username.clone(),
+                position,
+                rotation: Vector2::new(0.0, 0.0),
+            }));
+        }
+
+        if is_due(self.last_action, self.action_interval, now) {
+            self.last_action = Some(now);
+            self.actions += 1;
+            let block = Vector3::new(
+                position.x.floor() as i32,
+                position.y.floor() as i32 + EDIT_OFFSET,
+                position.z.floor() as i32,
+            );
+            let packet = match self.actions % 2 {
+                0 => self.edit_block(block, now),
+                _ => Some(self.request_chunk(block, now)),
+            };
+            packets.extend(packet);
+        }
+
+        packets
+    }
+
+    fn request_chunk(&mut self, block: Vector3<i32>, now: Instant) -> Packet {
+        let (x, z) = REQUEST_OFFSETS[self.actions / 2 % REQUEST_OFFSETS.len()];
+        let column = world_to_column_position(&Vector2::new(block.x, block.z)) + Vector2::new(x, z);
+        self.chunk_requests.push_back((column, now));
+        Packet::ChunkRequest(ChunkRequest {
+            column_position: column,
+            min_height: 0,
+            max_height: 0,
+        })
+    }
+
+    /// Places a block at `block`, or breaks it if the bot placed one there before
+    fn edit_block(&mut self, block: Vector3<i32>, now: Instant) -> Option<Packet> {
+        // The server refuses changes to chunks it has not sent
+        if !self
+            .loaded_chunks
+            .contains(&world_to_chunk_position(&block))
+            || self
+                .block_updates
+                .values()
+                .any(|(position, _)| *position == block)
+        {
+            return None;
+        }
+        let update_type = match self.placed.remove(&block) {
+            true => ChunkUpdateType::DestroyBlockEvent,
+            false => {
+                self.placed.insert(block);
+                ChunkUpdateType::PlaceBlockEvent
+            }
+        };
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.block_updates.insert(sequence, (block, now));
+        Some(Packet::ChunkUpdate(ChunkUpdate {
+            block_position: block,
+            update_type,
+            block_id: EDIT_BLOCK_ID,
+            sequence,
+        }))
+    }
+}
+
+fn is_due(last: Option<Instant>, interval: Duration, now: Instant) -> bool {
+    last.is_none_or(|last| now - last >= interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_path() {
+        let path = ScriptedPath::square(Vector3::new(0.0, 80.0, 0.0), 2.0, 1.0);
+        assert_eq!(
+            path.position_at(Duration::ZERO),
+            Vector3::new(-2.0, 80.0, -2.0)
+        );
+        assert_eq!(
+            path.position_at(Duration::from_secs(2)),
+            Vector3::new(0.0, 80.0, -2.0)
+        );
+        assert_eq!(
+            path.position_at(Duration::from_secs(8)),
+            Vector3::new(2.0, 80.0, 2.0)
+        );
+        // The path loops back to its start
+        assert_eq!(
+            path.position_at(Duration::from_secs(17)),
+            path.position_at(Duration::from_secs(1))
+        );
+    }
+}
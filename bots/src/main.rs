@@ -0,0 +1,123 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use bots::bot::{Bot, ScriptedPath};
+use bots::swarm::Swarm;
+use cgmath::Vector3;
+use common::cli::flag_value;
+use common::net::{resolve_address, DEFAULT_PORT};
+use common::packets::CHANNEL_COUNT;
+use common::transport::ip::IpTransport;
+use enet::Enet;
+
+/// How often progress is printed during a run
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+/// Bot paths are laid out in rows this many paths long
+const PATHS_PER_ROW: usize = 8;
+
+/// What the load test does
+struct BotOptions {
+    /// Address of the server, which may include a port
+    server: String,
+    bots: usize,
+    duration: Duration,
+    /// Chunk requests and block edits each bot makes per second, combined
+    actions_per_second: f32,
+    /// Blocks between the centers of neighbouring bots' paths
+    spread: f32,
+    /// Blocks each bot walks per second
+    speed: f32,
+    /// Bots are named this followed by a number
+    name: String,
+}
+
+impl BotOptions {
+    fn new() -> Self {
+        BotOptions {
+            server: "127.0.0.1".to_string(),
+            bots: 10,
+            duration: Duration::from_secs(60),
+            actions_per_second: 4.0,
+            spread: 48.0,
+            speed: 4.3,
+            name: "bot".to_string(),
+        }
+    }
+
+    fn parse_cli(mut self) -> Self {
+        let args: Vec<String> = env::args().collect();
+
+        if let Some(value) = flag_value(&args, "--server") {
+            self.server = value;
+        }
+        if let Some(value) = flag_value(&args, "--bots") {
+            self.bots = value;
+        }
+        if let Some(value) = flag_value(&args, "--duration") {
+            self.duration = Duration::from_secs(value);
+        }
+        if let Some(value) = flag_value(&args, "--actions_per_second") {
+            self.actions_per_second = value;
+        }
+        if let Some(value) = flag_value(&args, "--spread") {
+            self.spread = value;
+        }
+        if let Some(value) = flag_value(&args, "--speed") {
+            self.speed = value;
+        }
+        if let Some(value) = flag_value(&args, "--name") {
+            self.name = value;
+        }
+
+        self
+    }
+}
+
+fn main() -> Result<()> {
+    let options = BotOptions::new().parse_cli();
+    let address = resolve_address(&options.server, DEFAULT_PORT)?;
+
+    let enet = Enet::new().unwrap();
+    let transport = IpTransport::client(&enet, options.bots, CHANNEL_COUNT)?;
+    let mut swarm = Swarm::new(transport);
+    for i in 0..options.bots {
+        // Spread the bots out so they load different columns
+        let center = Vector3::new(
+            (i % PATHS_PER_ROW) as f32 * options.spread,
+            80.0,
+            (i / PATHS_PER_ROW) as f32 * options.spread,
+        );
+        let path = ScriptedPath::square(center, options.spread / 4.0, options.speed);
+        let name = format!("{}{}", options.name, i);
+        swarm.spawn(&address, Bot::new(&name, path, options.actions_per_second))?;
+    }
+    println!(
+        "Running {} bots against {} for {}s",
+        options.bots,
+        address,
+        options.duration.as_secs()
+    );
+
+    let started = Instant::now();
+    let mut last_status = started;
+    while started.elapsed() < options.duration && swarm.active() > 0 {
+        swarm.step(Duration::from_millis(5));
+
+        if last_status.elapsed() >= STATUS_INTERVAL {
+            last_status = Instant::now();
+            println!(
+                "{}s: {} of {} bots playing, {} packets received",
+                started.elapsed().as_secs(),
+                swarm.playing(),
+                options.bots,
+                swarm.stats.packets_received
+            );
+        }
+    }
+
+    swarm.disconnect_all(Duration::from_secs(1));
+    println!("{}", swarm.stats.report());
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+/// Round trip times of one kind of request
+#[derive(Default)]
+pub struct Latencies {
+    samples: Vec<Duration>,
+}
+
+impl Latencies {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The latency `percent` of the samples are at or below, or nothing without any samples
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+    }
+
+    /// Describes the spread of the samples in milliseconds
+    pub fn summary(&self) -> String {
+        if self.samples.is_empty() {
+            return "no samples".to_string();
+        }
+        let total: Duration = self.samples.iter().sum();
+        let mean = total / self.samples.len() as u32;
+        let ms = |latency: Option<Duration>| latency.unwrap_or_default().as_secs_f64() * 1000.0;
+        format!(
+            "{} samples, mean {:.1}ms, p50 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+            self.samples.len(),
+            ms(Some(mean)),
+            ms(self.percentile(50.0)),
+            ms(self.percentile(99.0)),
+            ms(self.percentile(100.0)),
+        )
+    }
+}
+
+/// Everything measured across all bots of a run
+pub struct Stats {
+    started: Instant,
+    pub logins: u32,
+    /// Bots whose connection closed before the run ended
+    pub disconnects: u32,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// From connecting until the server accepts the player
+    pub login_latency: Latencies,
+    /// From a chunk request until its contents arrive
+    pub chunk_latency: Latencies,
+    /// From placing or breaking a block until the server acks the change
+    pub block_latency: Latencies,
+    /// Block changes the server refused
+    pub rejected_edits: u32,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            started: Instant::now(),
+            logins: 0,
+            disconnects: 0,
+            packets_sent: 0,
+            bytes_sent: 0,
+            packets_received: 0,
+            bytes_received: 0,
+            login_latency: Latencies::default(),
+            chunk_latency: Latencies::default(),
+            block_latency: Latencies::default(),
+            rejected_edits: 0,
+        }
+    }
+
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += bytes as u64;
+    }
+
+    pub fn record_received(&mut self, bytes: usize) {
+        self.packets_received += 1;
+        self.bytes_received += bytes as u64;
+    }
+
+    /// Describes the run so far
+    pub fn report(&self) -> String {
+        let seconds = self.started.elapsed().as_secs_f64().max(f64::EPSILON);
+        format!(
+            "{} logins, {} disconnects over {:.1}s\n\
+             Sent {} packets ({:.1} per second, {:.1} KiB/s)\n\
+             Received {} packets ({:.1} per second, {:.1} KiB/s)\n\
+             Login latency: {}\n\
+             Chunk latency: {}\n\
+             Block latency: {}, {} rejected",
+            self.logins,
+            self.disconnects,
+            seconds,
+            self.packets_sent,
+            self.packets_sent as f64 / seconds,
+            self.bytes_sent as f64 / seconds / 1024.0,
+            self.packets_received,
+            self.packets_received as f64 / seconds,
+            self.bytes_received as f64 / seconds / 1024.0,
+            self.login_latency.summary(),
+            self.chunk_latency.summary(),
+            self.block_latency.summary(),
+            self.rejected_edits,
+        )
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut latencies = Latencies::default();
+        assert_eq!(latencies.percentile(50.0), None);
+        assert_eq!(latencies.summary(), "no samples");
+
+        for ms in (1..=100).rev() {
+            latencies.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latencies.count(), 100);
+        assert_eq!(latencies.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(latencies.percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(latencies.percentile(99.0), Some(Duration::from_millis(99)));
+        assert_eq!(
+            latencies.percentile(100.0),
+            Some(Duration::from_millis(100))
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use common::packets::Packet;
+use common::transport::{ConnectionId, Transport, TransportEvent};
+
+use crate::bot::{Bot, BotState};
+use crate::stats::Stats;
+
+/// Runs many bots over one transport, each on its own connection
+pub struct Swarm<T: Transport> {
+    transport: T,
+    bots: HashMap<ConnectionId, Bot>,
+    pub stats: Stats,
+}
+
+impl<T: Transport> Swarm<T> {
+    pub fn new(transport: T) -> Swarm<T> {
+        Swarm {
+            transport,
+            bots: HashMap::new(),
+            stats: Stats::new(),
+        }
+    }
+
+    /// Connects `bot` to the server at `address`
+    pub fn spawn(&mut self, address: &T::Address, bot: Bot) -> Result<()> {
+        let connection = self.transport.connect(address)?;
+        self.bots.insert(connection, bot);
+        Ok(())
+    }
+
+    /// Bots that have not been disconnected
+    pub fn active(&self) -> usize {
+        self.bots
+            .values()
+            .filter(|bot| bot.state != BotState::Closed)
+            .count()
+    }
+
+    /// Bots that are logged in
+    pub fn playing(&self) -> usize {
+        self.bots
+            .values()
+            .filter(|bot| bot.state == BotState::Playing)
+            .count()
+    }
+
+    /// Waits up to `timeout` for network events and handles every one that has arrived, then
+    /// sends whatever the bots want to
+    pub fn step(&mut self, timeout: Duration) {
+        let mut event = self.service(timeout);
+        while let Some(current) = event {
+            self.handle_event(current);
+            event = self.service(Duration::ZERO);
+        }
+
+        let now = Instant::now();
+        let updates: Vec<(ConnectionId, Vec<Packet>)> = self
+            .bots
+            .iter_mut()
+            .map(|(&connection, bot)| (connection, bot.update(now)))
+            .collect();
+        for (connection, packets) in updates {
+            for packet in packets {
+                self.send(connection, &packet);
+            }
+        }
+    }
+
+    /// Disconnects every bot and waits up to `timeout` for the connections to close
+    pub fn disconnect_all(&mut self, timeout: Duration) {
+        for (&connection, bot) in self.bots.iter_mut() {
+            if bot.state != BotState::Closed {
+                bot.state = BotState::Closed;
+                self.transport.disconnect(connection, 0);
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && self.transport.has_connections() {
+            self.service(Duration::from_millis(10));
+        }
+    }
+
+    fn service(&mut self, timeout: Duration) -> Option<TransportEvent> {
+        match self.transport.service(timeout) {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Unable to service the network: {}", e);
+                None
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: TransportEvent) {
+        match event {
+            TransportEvent::Connected(connection) => {
+                if let Some(bot) = self.bots.get_mut(&connection) {
+                    let packet = bot.connected();
+                    self.send(connection, &packet);
+                }
+            }
+            TransportEvent::Disconnected(connection, _) => {
+                if let Some(bot) = self.bots.get_mut(&connection) {
+                    if bot.state != BotState::Closed {
+                        bot.state = BotState::Closed;
+                        self.stats.disconnects += 1;
+                    }
+                }
+            }
+            TransportEvent::Received(connection, data) => {
+                self.stats.record_received(data.len());
+                let bot = match self.bots.get_mut(&connection) {
+                    Some(bot) => bot,
+                    None => return,
+                };
+                let packet = match Packet::decode(&data) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        eprintln!("{} could not decode a packet: {}", bot.username, e);
+                        return;
+                    }
+                };
+                if bot.handle_packet(packet, Instant::now(), &mut self.stats) {
+                    bot.state = BotState::Closed;
+                    self.transport.disconnect(connection, 0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, connection: ConnectionId, packet: &Packet) {
+        let compress = self
+            .bots
+            .get(&connection)
+            .is_some_and(|bot| bot.compresses());
+        let encoded = packet.encode_with(compress);
+        self.stats.record_sent(encoded.data.len());
+        if let Err(e) = self
+            .transport
+            .send(connection, packet.channel(), &encoded.data)
+        {
+            eprintln!("Unable to send packet: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::ScriptedPath;
+    use cgmath::Vector3;
+    use common::transport::loopback::LoopbackTransport;
+    use server::game::{Game, GameOptions};
+    use server::network::Server;
+    use server::save_file::SaveFile;
+
+    #[test]
+    fn test_swarm_against_server() {
+        let mut options = GameOptions::new();
+        options.view_distance = 1;
+        let server_transport = LoopbackTransport::new();
+        let address = server_transport.address();
+        // The server's scripts are in the repository root
+        let save = SaveFile::with_scripts_root(None, concat!(env!("CARGO_MANIFEST_DIR"), "/.."));
+        let mut server = Server::new(Game::new(options, save), server_transport);
+
+        let mut swarm = Swarm::new(LoopbackTransport::new());
+        for i in 0..3 {
+            let center = Vector3::new(i as f32 * 32.0, 80.0, 0.0);
+            let path = ScriptedPath::square(center, 4.0, 4.0);
+            swarm
+                .spawn(&address, Bot::new(&format!("bot{}", i), path, 50.0))
+                .unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline
+            && (swarm.stats.chunk_latency.count() < 3 || swarm.stats.block_latency.count() < 3)
+        {
+            server.tick();
+            swarm.step(Duration::from_millis(1));
+        }
+
+        assert_eq!(swarm.playing(), 3);
+        assert_eq!(swarm.stats.logins, 3);
+        assert!(swarm.stats.chunk_latency.count() >= 3);
+        assert!(swarm.stats.block_latency.count() >= 3);
+        assert!(swarm.stats.packets_received > 0);
+
+        swarm.disconnect_all(Duration::from_millis(100));
+        assert_eq!(swarm.active(), 0);
+        assert_eq!(swarm.stats.disconnects, 0);
+    }
+}
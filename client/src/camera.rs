@@ -1,4 +1,5 @@
-use cgmath::{Array, InnerSpace, Matrix4, Point3, SquareMatrix, Vector2, Vector3};
+use cgmath::{Array, InnerSpace, Matrix4, Point3, SquareMatrix, Vector2, Vector3, Zero};
+use common::{FLY_SPEED, FLY_SPEED_MODIFIER};
 use winit::event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
 
 pub struct Camera {
@@ -35,7 +36,7 @@ impl Camera {
 
     pub fn new(start_position: Vector3<f32>, config: &wgpu::SurfaceConfiguration) -> Self {
         let mut cam = Camera {
-            speed: 10.0,
+            speed: FLY_SPEED,
             sensitivity: 5.0,
 
             mouse_delta_position: Vector2::from_value(0.0),
@@ -75,39 +76,66 @@ impl Camera {
         self.proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
     }
 
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    /// Unit vector the camera is looking along
+    pub fn front(&self) -> Vector3<f32> {
+        self.front
+    }
+
+    /// Pitch and yaw in degrees
+    pub fn rotation(&self) -> Vector2<f32> {
+        Vector2::new(self.pitch, self.yaw)
+    }
+
+    /// Moves the camera to `position`, facing `rotation` as returned by `Camera::rotation`
+    pub fn teleport(&mut self, position: Vector3<f32>, rotation: Vector2<f32>) {
+        self.position = position;
+        self.pitch = rotation.x;
+        self.yaw = rotation.y;
+        self.calculate_view();
+    }
+
     pub fn update(&mut self, delta_time: f32) {
         let mut speed = self.speed * delta_time;
 
         if self.is_shift_pressed {
-            speed *= 5.0;
+            speed *= FLY_SPEED_MODIFIER;
         }
         if self.is_control_pressed {
-            speed *= 5.0;
+            speed *= FLY_SPEED_MODIFIER;
         }
         if self.is_alt_pressed {
-            speed /= 5.0;
+            speed /= FLY_SPEED_MODIFIER;
         }
 
         let previous_position = self.position.clone();
 
+        let mut direction = Vector3::zero();
         if self.is_forward_pressed {
-            self.position += self.front * speed;
+            direction += self.front;
         }
         if self.is_backward_pressed {
-            self.position -= self.front * speed;
+            direction -= self.front;
         }
         if self.is_left_pressed {
-            self.position -= self.front.cross(Self::UP_DIR).normalize() * speed;
+            direction -= self.front.cross(Self::UP_DIR).normalize();
         }
         if self.is_right_pressed {
-            self.position += self.front.cross(Self::UP_DIR).normalize() * speed;
+            direction += self.front.cross(Self::UP_DIR).normalize();
         }
-
         if self.is_space_pressed {
-            self.position.y += speed;
+            direction += Self::UP_DIR;
         }
         if self.is_c_pressed {
-            self.position.y -= speed;
+            direction -= Self::UP_DIR;
+        }
+        // Moving along several directions at once is no faster than along one, as the server
+        // only allows up to `MAX_FLY_SPEED`
+        if direction.magnitude2() > 0.0 {
+            self.position += direction.normalize() * speed;
         }
 
         let mouse_difference = Vector2::from(self.mouse_delta_position);
@@ -0,0 +1,34 @@
+use std::{
+    io::{self, BufRead},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use common::packets::{MessageKind, ServerMessage};
+
+/// Reads chat lines and commands typed into the terminal on a background thread
+pub fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Formats a message from the server for display
+pub fn format_message(message: &ServerMessage) -> String {
+    match (message.kind, &message.sender) {
+        (MessageKind::Chat, Some(sender)) => format!("<{}> {}", sender, message.message),
+        _ => format!("[Server] {}", message.message),
+    }
+}
@@ -1,30 +1,46 @@
 use std::{
+    env,
     fs::{self, File},
     io::Read,
-    net::Ipv4Addr,
-    time::SystemTime,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
 };
 
 use camera::{Camera, CameraUniform};
-use cgmath::{Array, Vector2, Vector3};
-use enet::{Address, BandwidthLimit, ChannelLimit, Enet, Packet, PacketMode};
-use packets::{assemble_player_connect_info, assemble_player_info_request};
+use cgmath::{Vector2, Vector3};
+use enet::Enet;
+use network::{query_status, Connection};
+use packets::{
+    assemble_chat_message, assemble_chunk_update, assemble_command, assemble_player_info_data,
+    assemble_player_info_request,
+};
+use prediction::Predictions;
 use wgpu::util::DeviceExt;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::{CursorGrabMode, Window, WindowBuilder},
 };
+use world::World;
 
 use anyhow::Result;
 use common::{
+    cli::flag_value,
+    is_valid_username,
     items::{ItemInfo, ItemManager, ItemType, TextureCoordinates},
-    Chunk, QuadVertex,
+    net::{resolve_address, DEFAULT_PORT},
+    packets::{ChunkUpdateType, Packet, CHANNEL_COUNT},
+    transport::ip::IpTransport,
+    QuadVertex, MAX_USERNAME_LENGTH,
 };
 
 mod camera;
+mod chat;
+mod network;
 mod packets;
+mod prediction;
 mod texture;
+mod world;
 
 fn read_file_as_bytes(path: &str) -> Result<Vec<u8>> {
     let mut in_file = File::open(path)?;
@@ -52,9 +68,9 @@ struct State {
     camera_bind_group: wgpu::BindGroup,
 
     render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
+
+    item_manager: ItemManager,
+    world: World,
 
     diffuse_bind_group: wgpu::BindGroup,
     depth_texture: texture::Texture,
@@ -302,26 +318,6 @@ impl State {
             ),
         });
 
-        let mut test_chunk = Chunk::new(
-            Vector3::from_value(0),
-            item_manager.get_id_by_name("Grass".to_owned()).unwrap(),
-        );
-        test_chunk.build_mesh(&item_manager);
-        println!("Building mesh!");
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&test_chunk.vertices.as_slice()),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&test_chunk.indices.as_slice()),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-
-        let num_indices = test_chunk.indices.len() as u32;
-
         Self {
             window,
             surface,
@@ -338,9 +334,9 @@ impl State {
             camera_bind_group,
 
             render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
+
+            item_manager,
+            world: World::new(),
             diffuse_bind_group,
             depth_texture,
         }
@@ -380,6 +376,8 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+
+        self.world.update_meshes(&self.device, &self.item_manager);
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -427,10 +425,7 @@ impl State {
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
             render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+            self.world.draw(&mut render_pass);
         }
 
         // submit will accept anything that implements IntoIter
@@ -441,8 +436,90 @@ impl State {
     }
 }
 
+/// Handles a packet received from the server
+fn handle_packet(world: &mut World, predictions: &mut Predictions, packet: Packet) {
+    match packet {
+        Packet::ChunkContents(contents) => {
+            for chunk in &contents.chunks {
+                world.load_chunk(chunk);
+            }
+        }
+        Packet::BlockChanged(change) => {
+            predictions.server_changed(world, change.position, change.block_id)
+        }
+        Packet::MultiBlockChange(changes) => {
+            for change in changes.changes {
+                predictions.server_changed(world, change.position, change.block_id);
+            }
+        }
+        Packet::ChunkUpdateAck(ack) => predictions.acknowledged(world, ack),
+        Packet::UnloadColumns(unload) => {
+            for column_position in &unload.column_positions {
+                world.unload_column(column_position);
+            }
+        }
+        Packet::ServerMessage(message) => println!("{}", chat::format_message(&message)),
+        packet => println!("Got a {:?} packet from the server!", packet.packet_type()),
+    }
+}
+
+/// Places or breaks the block the player is looking at, showing the change before the server
+/// has acked it
+fn edit_block(
+    state: &mut State,
+    predictions: &mut Predictions,
+    update_type: ChunkUpdateType,
+) -> Option<Packet> {
+    let (target, in_front) =
+        state
+            .world
+            .target_block(state.camera.position(), state.camera.front(), REACH)?;
+    let (position, block_id) = match update_type {
+        ChunkUpdateType::PlaceBlockEvent if in_front != target => (in_front, PLACED_BLOCK_ID),
+        ChunkUpdateType::PlaceBlockEvent => return None,
+        ChunkUpdateType::DestroyBlockEvent => (target, 0),
+    };
+    let sequence = predictions.predict(&mut state.world, position, block_id);
+    Some(assemble_chunk_update(
+        position,
+        update_type,
+        PLACED_BLOCK_ID,
+        sequence,
+    ))
+}
+
+/// Farthest away in blocks the player can place and break blocks
+const REACH: f32 = 8.0;
+/// Block placed with the right mouse button
+const PLACED_BLOCK_ID: i32 = 1;
+
+/// How often the player's position is sent to the server, which streams the world around it
+const PLAYER_INFO_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Server joined unless another is given with `--server <address>`
+const DEFAULT_SERVER: &str = "127.0.0.1";
+/// Name logged in with unless another is given with `--username <name>`
+const DEFAULT_USERNAME: &str = "ethan";
+
+/// How long `--status` waits for the server to answer
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Environment variable holding the password or login token of the player's account
+const PASSWORD_VARIABLE: &str = "VOXELBUILDER_PASSWORD";
+
 pub async fn run() {
     env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    let address = server_address(&args);
+    let username = flag_value(&args, "--username").unwrap_or(DEFAULT_USERNAME.to_string());
+    if !is_valid_username(&username) {
+        eprintln!(
+            "Usernames must be 1 to {} letters, digits or underscores",
+            MAX_USERNAME_LENGTH
+        );
+        std::process::exit(1);
+    }
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -451,90 +528,60 @@ pub async fn run() {
 
     let mut state = State::new(window).await;
 
+    let password = env::var(PASSWORD_VARIABLE).ok();
     let enet = Enet::new().unwrap();
-    let mut host = enet
-        .create_host::<()>(
-            None,
-            10,
-            ChannelLimit::Maximum,
-            BandwidthLimit::Unlimited,
-            BandwidthLimit::Unlimited,
-        )
-        .expect("Unable to start networking host");
-    host.connect(&Address::new(Ipv4Addr::LOCALHOST, 1234), 10, 0)
-        .expect("Could not connect to server");
-
-    let mut peer = loop {
-        let e = host.service(1000).expect("service failed");
-
-        let e = match e {
-            Some(e) => e,
-            _ => continue,
-        };
-
-        println!("[client] event: {:#?}", e);
-
-        match e {
-            enet::Event::Connect(ref p) => {
-                println!("Connected!");
-                break p.clone();
-            }
-            enet::Event::Disconnect(ref p, r) => {
-                println!("connection NOT successful, peer: {:?}, reason: {}", p, r);
+    let transport = match IpTransport::client(&enet, 1, CHANNEL_COUNT) {
+        Ok(transport) => transport,
+        Err(e) => {
+            eprintln!("Unable to create a network host: {}", e);
+            std::process::exit(0);
+        }
+    };
+    let mut connection =
+        match Connection::connect(transport, &address, &username, password.as_deref()) {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("Unable to join server: {}", e);
+                if password.is_none() {
+                    eprintln!(
+                        "If the server requires a password, set {}",
+                        PASSWORD_VARIABLE
+                    );
+                }
                 std::process::exit(0);
             }
-            enet::Event::Receive { .. } => {
-                panic!("unexpected Receive-event while waiting for connection")
-            }
         };
-    };
+    println!(
+        "Joined server with columns {} chunks tall",
+        connection.server_info.column_height
+    );
 
-    peer.send_packet(
-        Packet::new(
-            &assemble_player_connect_info("ethan"),
-            PacketMode::ReliableSequenced,
-        )
-        .unwrap(),
-        1,
-    )
-    .expect("Sending packet failed");
-
-    peer.send_packet(
-        Packet::new(
-            &&assemble_player_info_request("ethan"),
-            PacketMode::ReliableSequenced,
-        )
-        .unwrap(),
-        1,
-    )
-    .expect("Sending packet failed");
+    connection
+        .send(&assemble_player_info_request())
+        .expect("Sending packet failed");
 
+    let mut predictions = Predictions::new();
     loop {
-        match host.service(1000) {
-            Ok(e) => match e {
-                Some(e) => match &e {
-                    enet::Event::Connect(_) => {
-                        eprintln!("Someone trying to connect with the client?")
-                    }
-                    enet::Event::Disconnect(_, _) => {
-                        eprintln!("Disconnected while waiting for user info!");
-                        break;
-                    }
-                    enet::Event::Receive {
-                        sender: _,
-                        channel_id: _,
-                        packet: _,
-                    } => {
-                        println!("Got a message from the server!");
-                    }
-                },
-                None => continue,
-            },
-            Err(_) => eprintln!("Service failed!"),
+        match connection.receive(1000) {
+            Ok(Some(Packet::PlayerInfoData(info))) => {
+                println!("Got user info for {}", info.username);
+                state.camera.teleport(info.position, info.rotation);
+                break;
+            }
+            Ok(Some(packet)) => handle_packet(&mut state.world, &mut predictions, packet),
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(0);
+            }
         }
     }
 
+    let chat_lines = chat::spawn_stdin_reader();
+
     let mut delta_timer = SystemTime::now();
+    let mut player_info_timer = Instant::now();
+    let mut cursor_grabbed = true;
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::DeviceEvent { ref event, .. } => {
@@ -559,6 +606,7 @@ pub async fn run() {
                     WindowEvent::KeyboardInput { input, .. } => {
                         match (input.virtual_keycode, input.state) {
                             (Some(VirtualKeyCode::RControl), ElementState::Pressed) => {
+                                cursor_grabbed = false;
                                 state.window.set_cursor_visible(true);
                                 state.window.set_cursor_grab(CursorGrabMode::None).unwrap();
                             }
@@ -570,8 +618,24 @@ pub async fn run() {
                         button,
                         ..
                     } => match (button, button_state) {
+                        (MouseButton::Left | MouseButton::Right, ElementState::Pressed)
+                            if cursor_grabbed =>
+                        {
+                            let update_type = match button {
+                                MouseButton::Left => ChunkUpdateType::DestroyBlockEvent,
+                                _ => ChunkUpdateType::PlaceBlockEvent,
+                            };
+                            if let Some(packet) =
+                                edit_block(&mut state, &mut predictions, update_type)
+                            {
+                                if let Err(e) = connection.send(&packet) {
+                                    eprintln!("Unable to send block change: {}", e);
+                                }
+                            }
+                        }
                         (_, ElementState::Pressed) => {
                             if state.window.has_focus() {
+                                cursor_grabbed = true;
                                 state.window.set_cursor_visible(false);
                                 state
                                     .window
@@ -606,6 +670,44 @@ pub async fn run() {
             }
         }
         Event::MainEventsCleared => {
+            for line in chat_lines.try_iter() {
+                let packet = match line.strip_prefix('/') {
+                    Some(command) => assemble_command(command),
+                    None => assemble_chat_message(&line),
+                };
+                if let Err(e) = connection.send(&packet) {
+                    eprintln!("Unable to send chat message: {}", e);
+                }
+            }
+
+            if player_info_timer.elapsed() >= PLAYER_INFO_INTERVAL {
+                player_info_timer = Instant::now();
+                let packet = assemble_player_info_data(
+                    &username,
+                    state.camera.position(),
+                    state.camera.rotation(),
+                );
+                if let Err(e) = connection.send(&packet) {
+                    eprintln!("Unable to send player info: {}", e);
+                }
+            }
+
+            loop {
+                match connection.receive(0) {
+                    // Sent when the server moves the player, or refuses to let it move
+                    Ok(Some(Packet::PlayerInfoData(info))) if info.username == username => {
+                        state.camera.teleport(info.position, info.rotation)
+                    }
+                    Ok(Some(packet)) => handle_packet(&mut state.world, &mut predictions, packet),
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        *control_flow = ControlFlow::Exit;
+                        break;
+                    }
+                }
+            }
+
             // RedrawRequested will only trigger once, unless we manually
             // request it.
             state.window().request_redraw();
@@ -614,6 +716,44 @@ pub async fn run() {
     });
 }
 
+/// The server given with `--server`, exiting if it cannot be found
+fn server_address(args: &[String]) -> SocketAddr {
+    let server = flag_value(args, "--server").unwrap_or(DEFAULT_SERVER.to_string());
+    match resolve_address(&server, DEFAULT_PORT) {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Unable to find server {}: {}", server, e);
+            std::process::exit(0);
+        }
+    }
+}
+
+/// Prints the status of the server without logging in, for `--status`
+fn print_status() {
+    let args: Vec<String> = env::args().collect();
+    let address = server_address(&args);
+    let enet = Enet::new().unwrap();
+    let status = IpTransport::client(&enet, 1, CHANNEL_COUNT)
+        .and_then(|transport| query_status(transport, &address, STATUS_TIMEOUT));
+    match status {
+        Ok(status) => {
+            println!("{} ({})", status.motd, address);
+            println!("World: {}", status.world_name);
+            println!("Players: {}/{}", status.players, status.max_players);
+            println!("Uptime: {}s", status.uptime);
+            println!("Protocol version: {}", status.protocol_version);
+        }
+        Err(e) => {
+            eprintln!("Unable to query server {}: {}", address, e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
+    if env::args().any(|arg| arg == "--status") {
+        print_status();
+        return;
+    }
     pollster::block_on(run());
 }
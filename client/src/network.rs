@@ -0,0 +1,185 @@
+use anyhow::{anyhow, bail, Result};
+use common::{
+    auth::{challenge_response, hash_password},
+    packets::{
+        AuthResponse, Packet, PacketType, ServerInfo, StatusRequest, StatusResponse,
+        CAPABILITY_LZ4, PROTOCOL_VERSION,
+    },
+    transport::{ConnectionId, Transport, TransportEvent},
+    CHUNK_WIDTH, COLUMN_HEIGHT,
+};
+use std::time::{Duration, Instant};
+
+use crate::packets::assemble_player_connect_info;
+
+/// A handshaken connection to a server
+pub struct Connection<T: Transport> {
+    transport: T,
+    server: ConnectionId,
+    /// Facts about the server learned during the handshake
+    pub server_info: ServerInfo,
+}
+
+impl<T: Transport> Connection<T> {
+    /// Connects to the server at `address` and performs the connection handshake as `username`,
+    /// `password` is only needed if the player has an account on the server
+    pub fn connect(
+        mut transport: T,
+        address: &T::Address,
+        username: &str,
+        password: Option<&str>,
+    ) -> Result<Connection<T>> {
+        let server = open_connection(&mut transport, address)?;
+        println!("Connected!");
+
+        let mut connection = Connection {
+            transport,
+            server,
+            server_info: ServerInfo {
+                protocol_version: 0,
+                capabilities: 0,
+                chunk_width: 0,
+                column_height: 0,
+            },
+        };
+        connection.send(&assemble_player_connect_info(username))?;
+
+        connection.server_info = loop {
+            match connection.receive(1000)? {
+                Some(Packet::ServerInfo(info)) => break info,
+                Some(Packet::AuthChallenge(challenge)) => {
+                    let password = password.ok_or_else(|| {
+                        anyhow!("The server requires a password for {}", username)
+                    })?;
+                    let password_hash = hash_password(&challenge.salt, password);
+                    connection.send(&Packet::AuthResponse(AuthResponse {
+                        proof: challenge_response(&password_hash, &challenge.nonce),
+                    }))?;
+                }
+                Some(packet) => eprintln!(
+                    "Ignoring {:?} packet received during handshake",
+                    packet.packet_type()
+                ),
+                None => continue,
+            }
+        };
+
+        check_server_info(&connection.server_info)?;
+
+        Ok(connection)
+    }
+
+    /// Sends `packet` to the server
+    pub fn send(&mut self, packet: &Packet) -> Result<()> {
+        let compress = self.server_info.capabilities & CAPABILITY_LZ4 != 0;
+        let encoded = packet.encode_with(compress);
+        self.transport
+            .send(self.server, packet.channel(), &encoded.data)
+    }
+
+    /// Waits up to `timeout_ms` for the next packet from the server, failing with the server's
+    /// reason if it closes the connection
+    pub fn receive(&mut self, timeout_ms: u32) -> Result<Option<Packet>> {
+        match self
+            .transport
+            .service(Duration::from_millis(timeout_ms as u64))?
+        {
+            Some(TransportEvent::Received(_, data)) => match Packet::decode(&data) {
+                Ok(Packet::Disconnect(d)) => bail!("Disconnected by the server: {}", d.reason),
+                Ok(packet) => Ok(Some(packet)),
+                Err(e) => {
+                    eprintln!("Unable to decode packet from the server: {}", e);
+                    Ok(None)
+                }
+            },
+            Some(TransportEvent::Disconnected(..)) => bail!("Disconnected from the server"),
+            Some(TransportEvent::Connected(_)) => {
+                eprintln!("Someone trying to connect with the client?");
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Fails if the server's world is laid out differently from the one this client was built for, as
+/// the client would accept its chunks and then draw them in the wrong places
+fn check_server_info(info: &ServerInfo) -> Result<()> {
+    if info.protocol_version != PROTOCOL_VERSION {
+        bail!(
+            "Server uses protocol version {} but this client uses {}",
+            info.protocol_version,
+            PROTOCOL_VERSION
+        );
+    }
+    if info.chunk_width != CHUNK_WIDTH {
+        bail!(
+            "Server uses chunks {} blocks wide but this client only supports {}",
+            info.chunk_width,
+            CHUNK_WIDTH
+        );
+    }
+    if info.column_height != COLUMN_HEIGHT {
+        bail!(
+            "Server uses columns {} chunks tall but this client only supports {}",
+            info.column_height,
+            COLUMN_HEIGHT
+        );
+    }
+    Ok(())
+}
+
+/// Connects to the server at `address` and waits for the connection to open
+fn open_connection<T: Transport>(transport: &mut T, address: &T::Address) -> Result<ConnectionId> {
+    let server = transport.connect(address)?;
+
+    loop {
+        match transport.service(Duration::from_millis(1000))? {
+            Some(TransportEvent::Connected(id)) if id == server => return Ok(server),
+            Some(TransportEvent::Disconnected(_, r)) => {
+                bail!("Connection NOT successful, reason: {}", r)
+            }
+            Some(_) => bail!("Unexpected event while waiting for connection"),
+            None => continue,
+        }
+    }
+}
+
+/// Asks the server at `address` to describe itself without logging in, waiting up to `timeout`
+/// for the answer
+pub fn query_status<T: Transport>(
+    mut transport: T,
+    address: &T::Address,
+    timeout: Duration,
+) -> Result<StatusResponse> {
+    let deadline = Instant::now() + timeout;
+    let server = open_connection(&mut transport, address)?;
+    let request = Packet::StatusRequest(StatusRequest {});
+    transport.send(server, request.channel(), &request.encode())?;
+
+    while Instant::now() < deadline {
+        match transport.service(Duration::from_millis(100))? {
+            Some(TransportEvent::Received(_, data)) => match Packet::decode(&data) {
+                Ok(Packet::StatusResponse(status)) => return Ok(status),
+                Ok(Packet::Disconnect(d)) => bail!("Disconnected by the server: {}", d.reason),
+                Ok(packet) => eprintln!(
+                    "Ignoring {:?} packet received while waiting for the status",
+                    packet.packet_type()
+                ),
+                // A server using another protocol version may still send a readable version
+                Err(e) => match data.get(1..5) {
+                    Some(version) if data[0] == PacketType::StatusResponse as u8 => bail!(
+                        "The server uses protocol version {}, but this client uses {}",
+                        u32::from_le_bytes(version.try_into().unwrap()),
+                        PROTOCOL_VERSION
+                    ),
+                    _ => bail!("Unable to read the status: {}", e),
+                },
+            },
+            Some(TransportEvent::Disconnected(..)) => bail!("Disconnected from the server"),
+            Some(TransportEvent::Connected(_)) | None => (),
+        }
+    }
+
+    bail!("The server did not answer within {}s", timeout.as_secs())
+}
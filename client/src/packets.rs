@@ -1,29 +1,56 @@
-use common::packets::PacketType;
-
-pub fn assemble_player_connect_info(username: &str) -> Vec<u8> {
-    let mut packet_data = Vec::<u8>::new();
-
-    packet_data.push(PacketType::PlayerConnect as u8);
-
-    // username
-    for c in username.as_bytes() {
-        packet_data.push(*c);
-    }
-    packet_data.push('\0' as u8);
+use cgmath::{Vector2, Vector3};
+use common::packets::{
+    ChatMessage, ChunkUpdate, ChunkUpdateType, Command, Packet, PlayerConnect, PlayerInfoData,
+    PlayerInfoRequest, PROTOCOL_VERSION, SUPPORTED_CAPABILITIES,
+};
+
+pub fn assemble_player_connect_info(username: &str) -> Packet {
+    Packet::PlayerConnect(PlayerConnect {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: SUPPORTED_CAPABILITIES,
+        username: username.to_string(),
+    })
+}
 
-    packet_data
+pub fn assemble_player_info_request() -> Packet {
+    Packet::PlayerInfoRequest(PlayerInfoRequest {})
 }
 
-pub fn assemble_player_info_request(username: &str) -> Vec<u8> {
-    let mut packet_data = Vec::<u8>::new();
+/// Reports where the player is, the server streams the columns around this position
+pub fn assemble_player_info_data(
+    username: &str,
+    position: Vector3<f32>,
+    rotation: Vector2<f32>,
+) -> Packet {
+    Packet::PlayerInfoData(PlayerInfoData {
+        username: username.to_string(),
+        position,
+        rotation,
+    })
+}
 
-    packet_data.push(PacketType::PlayerInfoRequest as u8);
+pub fn assemble_chat_message(message: &str) -> Packet {
+    Packet::ChatMessage(ChatMessage {
+        message: message.to_string(),
+    })
+}
 
-    // username
-    for c in username.as_bytes() {
-        packet_data.push(*c);
-    }
-    packet_data.push('\0' as u8);
+pub fn assemble_command(command: &str) -> Packet {
+    Packet::Command(Command {
+        command: command.to_string(),
+    })
+}
 
-    packet_data
+pub fn assemble_chunk_update(
+    block_position: Vector3<i32>,
+    update_type: ChunkUpdateType,
+    block_id: i32,
+    sequence: u32,
+) -> Packet {
+    Packet::ChunkUpdate(ChunkUpdate {
+        block_position,
+        update_type,
+        block_id,
+        sequence,
+    })
 }
@@ -0,0 +1,96 @@
+use cgmath::Vector3;
+use common::packets::{ChunkUpdateAck, ChunkUpdateOutcome};
+
+use crate::world::World;
+
+/// A block change shown before the server has acked it
+struct PendingEdit {
+    sequence: u32,
+    position: Vector3<i32>,
+    /// The block the server last said is at `position`, shown again if the change is rejected
+    confirmed: i32,
+}
+
+/// Block changes made by the player that are shown straight away, and undone if the server
+/// rejects them
+pub struct Predictions {
+    next_sequence: u32,
+    /// In the order they were made
+    pending: Vec<PendingEdit>,
+}
+
+impl Predictions {
+    pub fn new() -> Predictions {
+        Predictions {
+            next_sequence: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Changes the block at `position` to `block_id`, returning the sequence number to send with
+    /// the change
+    pub fn predict(&mut self, world: &mut World, position: Vector3<i32>, block_id: i32) -> u32 {
+        let confirmed = self
+            .pending
+            .iter()
+            .find(|edit| edit.position == position)
+            .map(|edit| edit.confirmed)
+            .or_else(|| world.get_block(&position))
+            .unwrap_or(0);
+        world.set_block(&position, block_id);
+
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.pending.push(PendingEdit {
+            sequence,
+            position,
+            confirmed,
+        });
+        sequence
+    }
+
+    /// Applies a change made by the server, which is held back while the player's own changes to
+    /// the same block are waiting
+    pub fn server_changed(&mut self, world: &mut World, position: Vector3<i32>, block_id: i32) {
+        let mut predicted = false;
+        for edit in self
+            .pending
+            .iter_mut()
+            .filter(|edit| edit.position == position)
+        {
+            edit.confirmed = block_id;
+            predicted = true;
+        }
+        if !predicted {
+            world.set_block(&position, block_id);
+        }
+    }
+
+    /// Forgets an acked change, undoing it if the server rejected it
+    pub fn acknowledged(&mut self, world: &mut World, ack: ChunkUpdateAck) {
+        let index = match self
+            .pending
+            .iter()
+            .position(|edit| edit.sequence == ack.sequence)
+        {
+            Some(index) => index,
+            None => {
+                eprintln!("Got an ack for unknown block change {}", ack.sequence);
+                return;
+            }
+        };
+        let edit = self.pending.remove(index);
+
+        if let ChunkUpdateOutcome::Rejected { reason } = ack.outcome {
+            println!("{}", reason);
+            // Later changes to the block stay shown until they are acked themselves
+            if !self
+                .pending
+                .iter()
+                .any(|other| other.position == edit.position)
+            {
+                world.set_block(&edit.position, edit.confirmed);
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use common::{
+    coordinates::{world_to_chunk_position, world_to_position_in_chunk},
+    items::ItemManager,
+    packets::ChunkData,
+    Chunk,
+};
+use wgpu::util::DeviceExt;
+
+/// GPU buffers holding the mesh of one chunk
+struct ChunkMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+struct LoadedChunk {
+    chunk: Chunk,
+    mesh: Option<ChunkMesh>,
+    /// Set when the blocks changed after the mesh was built
+    needs_mesh: bool,
+}
+
+/// The chunks received from the server
+pub struct World {
+    chunks: HashMap<Vector3<i32>, LoadedChunk>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Adds or replaces the chunk sent by the server
+    pub fn load_chunk(&mut self, data: &ChunkData) {
+        let chunk = match data.to_chunk() {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                eprintln!("Unable to load chunk from the server: {}", e);
+                return;
+            }
+        };
+
+        self.chunks.insert(
+            data.position,
+            LoadedChunk {
+                chunk,
+                mesh: None,
+                needs_mesh: true,
+            },
+        );
+    }
+
+    /// Drops every chunk of the column at `column_position`
+    pub fn unload_column(&mut self, column_position: &Vector2<i32>) {
+        self.chunks.retain(|position, _| {
+            position.x != column_position.x || position.z != column_position.y
+        });
+    }
+
+    /// The block at the world `position`, if its chunk is loaded
+    pub fn get_block(&self, position: &Vector3<i32>) -> Option<i32> {
+        let loaded = self.chunks.get(&world_to_chunk_position(position))?;
+        let position_in_chunk = world_to_position_in_chunk(position);
+        Some(loaded.chunk.get_block(
+            position_in_chunk.x as u8,
+            position_in_chunk.y as u8,
+            position_in_chunk.z as u8,
+        ))
+    }
+
+    /// The first solid block within `reach` of `origin` along `direction`, along with the block
+    /// in front of it that was passed through last
+    pub fn target_block(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        reach: f32,
+    ) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        // A ray without a direction would never cross a block boundary
+        let finite = |v: Vector3<f32>| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        if !finite(origin) || !finite(direction) || direction == Vector3::new(0.0, 0.0, 0.0) {
+            return None;
+        }
+        let direction = direction.normalize();
+        let mut block = origin.map(|v| v.floor() as i32);
+        let mut previous = block;
+        let step = direction.map(|v| match v {
+            v if v > 0.0 => 1,
+            v if v < 0.0 => -1,
+            _ => 0,
+        });
+        // Distance along the ray between crossings of each axis, and to the next crossing
+        let delta = direction.map(|v| 1.0 / v.abs());
+        let mut next = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        for axis in 0..3 {
+            let boundary = match step[axis] {
+                1 => block[axis] as f32 + 1.0 - origin[axis],
+                -1 => origin[axis] - block[axis] as f32,
+                _ => continue,
+            };
+            next[axis] = boundary * delta[axis];
+        }
+
+        loop {
+            if self.get_block(&block).is_some_and(|id| id > 0) {
+                return Some((block, previous));
+            }
+            let axis = match (next.x < next.y, next.x < next.z, next.y < next.z) {
+                (true, true, _) => 0,
+                (false, _, true) => 1,
+                _ => 2,
+            };
+            if next[axis] > reach {
+                return None;
+            }
+            previous = block;
+            block[axis] += step[axis];
+            next[axis] += delta[axis];
+        }
+    }
+
+    /// Sets the block at the world `position` to `id` if its chunk is loaded
+    pub fn set_block(&mut self, position: &Vector3<i32>, id: i32) {
+        let loaded = match self.chunks.get_mut(&world_to_chunk_position(position)) {
+            Some(loaded) => loaded,
+            None => return,
+        };
+
+        let position_in_chunk = world_to_position_in_chunk(position);
+        loaded.chunk.set_block(
+            position_in_chunk.x as u8,
+            position_in_chunk.y as u8,
+            position_in_chunk.z as u8,
+            id,
+        );
+        loaded.needs_mesh = true;
+    }
+
+    /// Rebuilds the mesh of every chunk that changed since it was last built
+    pub fn update_meshes(&mut self, device: &wgpu::Device, item_manager: &ItemManager) {
+        for loaded in self.chunks.values_mut().filter(|loaded| loaded.needs_mesh) {
+            loaded.chunk.build_mesh(item_manager);
+            loaded.mesh = Some(ChunkMesh {
+                vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Chunk Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&loaded.chunk.vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }),
+                index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Chunk Index Buffer"),
+                    contents: bytemuck::cast_slice(&loaded.chunk.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                }),
+                num_indices: loaded.chunk.indices.len() as u32,
+            });
+            loaded.needs_mesh = false;
+        }
+    }
+
+    /// Draws every chunk that has a mesh, the pipeline and bind groups must already be set
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        for mesh in self
+            .chunks
+            .values()
+            .filter_map(|loaded| loaded.mesh.as_ref())
+        {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        }
+    }
+}
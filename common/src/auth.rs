@@ -0,0 +1,139 @@
+use sha2::{Digest, Sha256};
+
+/// Length in bytes of a password salt
+pub const SALT_LENGTH: usize = 16;
+/// Length in bytes of the nonce sent with an authentication challenge
+pub const NONCE_LENGTH: usize = 32;
+/// How many times a password is rehashed, making stolen hashes slower to brute force
+const PASSWORD_HASH_ROUNDS: u32 = 10_000;
+
+pub type Hash = [u8; 32];
+
+/// Hashes `password` with `salt`, the result is what the server stores for an account
+pub fn hash_password(salt: &[u8; SALT_LENGTH], password: &str) -> Hash {
+    let mut hash: Hash = Sha256::new()
+        .chain_update(salt)
+        .chain_update(password.as_bytes())
+        .finalize()
+        .into();
+    for _ in 1..PASSWORD_HASH_ROUNDS {
+        hash = Sha256::new()
+            .chain_update(salt)
+            .chain_update(hash)
+            .finalize()
+            .into();
+    }
+
+    hash
+}
+
+/// Derives the key the client proves it knows from its password hash. Only a hash of this key is
+/// stored, so the stored value cannot answer a challenge itself
+pub fn client_key(password_hash: &Hash) -> Hash {
+    hmac(password_hash, b"Client Key")
+}
+
+/// What the server stores for an account, enough to check responses but not to make them
+pub fn stored_key(client_key: &Hash) -> Hash {
+    Sha256::digest(client_key).into()
+}
+
+/// Proves knowledge of `password_hash` for a single challenge without sending anything the
+/// server stores, in the same way as SCRAM: the client key masked by a signature of the nonce
+/// made with the stored key
+pub fn challenge_response(password_hash: &Hash, nonce: &[u8; NONCE_LENGTH]) -> Hash {
+    let client_key = client_key(password_hash);
+    let signature = hmac(&stored_key(&client_key), nonce);
+    xor(&client_key, &signature)
+}
+
+/// Checks a response to the challenge that used `nonce` against the account's stored key
+pub fn verify_response(stored: &Hash, nonce: &[u8; NONCE_LENGTH], proof: &Hash) -> bool {
+    let client_key = xor(proof, &hmac(stored, nonce));
+    let expected = stored_key(&client_key);
+
+    // Compare every byte so the time taken does not reveal how much of the proof was right
+    expected
+        .iter()
+        .zip(stored.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+/// HMAC-SHA256 of `message` with a key no longer than a block
+fn hmac(key: &Hash, message: &[u8]) -> Hash {
+    const BLOCK_SIZE: usize = 64;
+    let mut inner_pad = [0x36; BLOCK_SIZE];
+    let mut outer_pad = [0x5c; BLOCK_SIZE];
+    for (i, byte) in key.iter().enumerate() {
+        inner_pad[i] ^= byte;
+        outer_pad[i] ^= byte;
+    }
+
+    let inner = Sha256::new()
+        .chain_update(inner_pad)
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(outer_pad)
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+fn xor(a: &Hash, b: &Hash) -> Hash {
+    let mut result = *a;
+    for (byte, other) in result.iter_mut().zip(b) {
+        *byte ^= other;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_response() {
+        let salt = [7; SALT_LENGTH];
+        let hash = hash_password(&salt, "hunter2");
+        assert_eq!(hash, hash_password(&salt, "hunter2"));
+        assert_ne!(hash, hash_password(&salt, "hunter3"));
+        assert_ne!(hash, hash_password(&[8; SALT_LENGTH], "hunter2"));
+
+        let nonce = [1; NONCE_LENGTH];
+        let proof = challenge_response(&hash, &nonce);
+        assert_eq!(
+            proof,
+            challenge_response(&hash_password(&salt, "hunter2"), &nonce)
+        );
+        assert_ne!(proof, challenge_response(&hash, &[2; NONCE_LENGTH]));
+
+        let stored = stored_key(&client_key(&hash));
+        assert!(verify_response(&stored, &nonce, &proof));
+        assert!(!verify_response(&stored, &[2; NONCE_LENGTH], &proof));
+
+        // What the server stores is not enough to answer a challenge
+        assert!(!verify_response(
+            &stored,
+            &nonce,
+            &challenge_response(&stored, &nonce)
+        ));
+    }
+
+    #[test]
+    fn test_hmac() {
+        // RFC 4231 test case 2, with the key padded to a hash's length with zeroes, which HMAC
+        // does anyway
+        let mut key = [0; 32];
+        key[..4].copy_from_slice(b"Jefe");
+        assert_eq!(
+            hmac(&key, b"what do ya want for nothing?"),
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+                0x64, 0xec, 0x38, 0x43
+            ]
+        );
+    }
+}
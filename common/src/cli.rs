@@ -0,0 +1,13 @@
+use std::str::FromStr;
+
+/// Parses the argument following `flag`, if it was given
+pub fn flag_value<T: FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    match args.get(index + 1).map(|value| value.parse()) {
+        Some(Ok(value)) => Some(value),
+        _ => {
+            eprintln!("Ignoring {} as it is not followed by a valid value", flag);
+            None
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use cgmath::{Vector2, Vector3};
+
+use crate::CHUNK_WIDTH;
+
+/// Translates absolute world position to absolute column position
+pub fn world_to_column_position(pos: &Vector2<i32>) -> Vector2<i32> {
+    pos.map(|axis| axis.div_euclid(CHUNK_WIDTH as i32))
+}
+
+/// Translates absolute world position to absolute chunk position
+pub fn world_to_chunk_position(pos: &Vector3<i32>) -> Vector3<i32> {
+    pos.map(|axis| axis.div_euclid(CHUNK_WIDTH as i32))
+}
+
+/// Translates absolute world position into internal chunk position
+pub fn world_to_position_in_chunk(pos: &Vector3<i32>) -> Vector3<i32> {
+    pos.map(|axis| axis.rem_euclid(CHUNK_WIDTH as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_world_to_column_position() {
+        // Positive
+        for x in 0..48 {
+            for z in 0..48 {
+                assert_eq!(
+                    world_to_column_position(&Vector2::new(x, z)),
+                    Vector2::new(x / 16, z / 16)
+                );
+            }
+        }
+
+        // Negative
+        for x in -49..0 {
+            for z in -49..0 {
+                let mut correct_x = x / 16;
+                if x < 0 && -x % 16 != 0 {
+                    correct_x -= 1;
+                }
+                let mut correct_z = z / 16;
+                if z < 0 && -z % 16 != 0 {
+                    correct_z -= 1;
+                }
+                assert_eq!(
+                    world_to_column_position(&Vector2::new(x, z)),
+                    Vector2::new(correct_x, correct_z)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_world_to_chunk_position() {
+        // Positive
+        for x in 0..48 {
+            for y in 0..48 {
+                for z in 0..48 {
+                    assert_eq!(
+                        world_to_chunk_position(&Vector3::new(x, y, z)),
+                        Vector3::new(x / 16, y / 16, z / 16)
+                    );
+                }
+            }
+        }
+
+        // Negative
+        for x in -49..0 {
+            for y in -49..0 {
+                for z in -49..0 {
+                    let mut correct_x = x / 16;
+                    if x < 0 && -x % 16 != 0 {
+                        correct_x -= 1;
+                    }
+                    let mut correct_y = y / 16;
+                    if y < 0 && -y % 16 != 0 {
+                        correct_y -= 1;
+                    }
+                    let mut correct_z = z / 16;
+                    if z < 0 && -z % 16 != 0 {
+                        correct_z -= 1;
+                    }
+                    assert_eq!(
+                        world_to_chunk_position(&Vector3::new(x, y, z)),
+                        Vector3::new(correct_x, correct_y, correct_z)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_world_to_position_in_chunk() {
+        // Chunk at 0,0,0
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    assert_eq!(
+                        world_to_position_in_chunk(&Vector3::new(x, y, z)),
+                        Vector3::new(x, y, z)
+                    );
+                }
+            }
+        }
+
+        // Chunk at 16,16,16
+        for x in 16..32 {
+            for y in 16..32 {
+                for z in 16..32 {
+                    assert_eq!(
+                        world_to_position_in_chunk(&Vector3::new(x, y, z)),
+                        Vector3::new(x - 16, y - 16, z - 16)
+                    );
+                }
+            }
+        }
+
+        // Chunk at -15,-15,-15
+        for x in -16..0 {
+            for y in -16..0 {
+                for z in -16..0 {
+                    assert_eq!(
+                        world_to_position_in_chunk(&Vector3::new(x, y, z)),
+                        Vector3::new(x + 16, y + 16, z + 16)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_extreme_positions() {
+        let min = Vector3::new(i32::MIN, i32::MIN, i32::MIN);
+        assert_eq!(world_to_chunk_position(&min), min / 16);
+        assert_eq!(world_to_position_in_chunk(&min), Vector3::new(0, 0, 0));
+
+        let max = Vector3::new(i32::MAX, i32::MAX, i32::MAX);
+        assert_eq!(world_to_chunk_position(&max), max / 16);
+        assert_eq!(world_to_position_in_chunk(&max), Vector3::new(15, 15, 15));
+    }
+}
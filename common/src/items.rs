@@ -47,6 +47,13 @@ impl ItemManager {
         None
     }
 
+    /// Whether the item with `item_id` can be placed in the world as a block
+    pub fn is_placeable_block(&self, item_id: i32) -> bool {
+        self.get_item_by_id(item_id).is_some_and(|info| {
+            matches!(info.item_type, ItemType::BlockCube | ItemType::BlockCross)
+        })
+    }
+
     pub fn is_transparent(&self, item_id: i32) -> Option<bool> {
         match self.get_item_by_id(item_id) {
             Some(info) => Some(info.is_transparent),
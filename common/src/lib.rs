@@ -1,13 +1,39 @@
+use anyhow::{bail, Result};
 use cgmath::Vector3;
 use items::ItemManager;
 use serde::{Deserialize, Serialize};
 
+pub mod auth;
+pub mod cli;
+pub mod coordinates;
 pub mod items;
+pub mod net;
 pub mod packets;
+pub mod transport;
+
+/// Width of a chunk in blocks along each axis
+pub const CHUNK_WIDTH: u8 = 16;
+/// Height of a chunk column in chunks
+pub const COLUMN_HEIGHT: u8 = 16;
+/// Blocks each second the client flies without a speed modifier held
+pub const FLY_SPEED: f32 = 10.0;
+/// How much each of the client's speed modifiers multiplies or divides its speed by
+pub const FLY_SPEED_MODIFIER: f32 = 5.0;
+/// Fastest the client flies, with both of its speed up modifiers held
+pub const MAX_FLY_SPEED: f32 = FLY_SPEED * FLY_SPEED_MODIFIER * FLY_SPEED_MODIFIER;
+/// Longest username a player can log in with
+pub const MAX_USERNAME_LENGTH: usize = 16;
+
+/// Whether `username` is 1 to `MAX_USERNAME_LENGTH` ASCII letters, digits or underscores. The
+/// server names each player's save file after them, so nothing else is allowed
+pub fn is_valid_username(username: &str) -> bool {
+    (1..=MAX_USERNAME_LENGTH).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
 
-const CHUNK_WIDTH: u8 = 16;
-
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub struct CompressedSet {
     pub id: i32,
     pub count: i32,
@@ -60,6 +86,25 @@ impl Chunk {
         }
     }
 
+    /// Creates a chunk from data produced by `Chunk::compress`
+    pub fn decompress(position: Vector3<i32>, data: &[CompressedSet]) -> Result<Chunk> {
+        let mut chunk = Chunk::new(position, 0);
+
+        let mut i = 0;
+        for set in data {
+            if set.count < 0 || i + set.count as usize > chunk.blocks.len() {
+                bail!("Compressed chunk data does not fit in a chunk");
+            }
+            chunk.blocks[i..i + set.count as usize].fill(set.id);
+            i += set.count as usize;
+        }
+        if i != chunk.blocks.len() {
+            bail!("Compressed chunk data only covers {} blocks", i);
+        }
+
+        Ok(chunk)
+    }
+
     pub fn xyz_to_i(x: u8, y: u8, z: u8) -> u16 {
         (CHUNK_WIDTH as u16).pow(2) * z as u16 + (CHUNK_WIDTH as u16) * y as u16 + x as u16
     }
@@ -108,14 +153,17 @@ impl Chunk {
         set
     }
 
+    /// Builds the mesh of the chunk with vertices in world space
     pub fn build_mesh(&mut self, item_manger: &ItemManager) {
         self.vertices.clear();
         self.indices.clear();
 
+        let chunk_offset = self.position.cast::<f32>().unwrap() * CHUNK_WIDTH as f32;
+
         for z in 0..CHUNK_WIDTH {
             for y in 0..CHUNK_WIDTH {
                 for x in 0..CHUNK_WIDTH {
-                    let block_offset = Vector3::new(x as f32, y as f32, z as f32);
+                    let block_offset = chunk_offset + Vector3::new(x as f32, y as f32, z as f32);
                     let block_id = self.blocks[Self::xyz_to_i(x, y, z) as usize];
                     let block_info = match item_manger.get_item_by_id(block_id) {
                         Some(item_info) => item_info,
@@ -486,3 +534,26 @@ impl Chunk {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_decompress() {
+        let mut chunk = Chunk::new(Vector3::new(1, 2, 3), 1);
+        chunk.set_block(4, 5, 6, 2);
+        chunk.set_block(15, 15, 15, 3);
+
+        let decompressed = Chunk::decompress(chunk.position, &chunk.compress()).unwrap();
+        assert_eq!(decompressed.blocks, chunk.blocks);
+
+        assert!(
+            Chunk::decompress(chunk.position, &[CompressedSet { id: 1, count: 4095 }]).is_err()
+        );
+        assert!(
+            Chunk::decompress(chunk.position, &[CompressedSet { id: 1, count: 4097 }]).is_err()
+        );
+        assert!(Chunk::decompress(chunk.position, &[CompressedSet { id: 1, count: -1 }]).is_err());
+    }
+}
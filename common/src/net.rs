@@ -0,0 +1,61 @@
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use anyhow::{anyhow, Result};
+
+/// Port the server listens on unless told otherwise
+pub const DEFAULT_PORT: u16 = 1234;
+
+/// Resolves `address`, an IP address or hostname optionally followed by `:port`, where IPv6
+/// addresses with a port are written `[address]:port`. Hostnames with both kinds of address
+/// resolve to their IPv4 one, which is served over enet rather than TCP
+pub fn resolve_address(address: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+    if let Ok(socket_address) = address.parse::<SocketAddr>() {
+        return Ok(socket_address);
+    }
+
+    let resolved: Vec<SocketAddr> = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| anyhow!("Invalid port in address {}", address))?;
+            (host, port).to_socket_addrs()?.collect()
+        }
+        None => (address, default_port).to_socket_addrs()?.collect(),
+    };
+    resolved
+        .iter()
+        .find(|socket_address| socket_address.is_ipv4())
+        .or_else(|| resolved.first())
+        .copied()
+        .ok_or_else(|| anyhow!("{} does not resolve to any address", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_resolve_address() {
+        assert_eq!(
+            resolve_address("127.0.0.1", DEFAULT_PORT).unwrap(),
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_PORT)
+        );
+        assert_eq!(
+            resolve_address("0.0.0.0:4321", DEFAULT_PORT).unwrap(),
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 4321)
+        );
+        assert_eq!(
+            resolve_address("::1", DEFAULT_PORT).unwrap(),
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DEFAULT_PORT)
+        );
+        assert_eq!(
+            resolve_address("[::]:4321", DEFAULT_PORT).unwrap(),
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 4321)
+        );
+        assert!(resolve_address("127.0.0.1:port", DEFAULT_PORT).is_err());
+    }
+}
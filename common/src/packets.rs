@@ -1,14 +1,57 @@
-#[allow(dead_code)]
+use std::fmt;
+
+use anyhow::Result;
+use cgmath::{Vector2, Vector3};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::auth::{Hash, NONCE_LENGTH, SALT_LENGTH};
+use crate::{Chunk, CompressedSet, COLUMN_HEIGHT};
+
+/// Version of the network protocol, bumped whenever the wire format changes
+pub const PROTOCOL_VERSION: u32 = 13;
+/// Capability allowing large payloads to be compressed with LZ4
+pub const CAPABILITY_LZ4: u32 = 1 << 0;
+/// Optional protocol features understood by this build, see `PlayerConnect::capabilities`
+pub const SUPPORTED_CAPABILITIES: u32 = CAPABILITY_LZ4;
+/// Payloads shorter than this are never compressed, the savings would not be worth the time
+pub const COMPRESSION_THRESHOLD: usize = 256;
+/// Longest chat message, in characters, that the server will relay
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 256;
+
+/// Channels a connection needs, one for each `Channel`
+pub const CHANNEL_COUNT: usize = 2;
+
+/// The enet channel a packet is sent on, so bulk chunk data does not hold up control messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// The handshake, chat, commands and player updates
+    Control,
+    /// Chunk contents, and everything that has to arrive in order with them such as block changes
+    Chunks,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PacketType {
     PlayerConnect,
     PlayerDisconnect,
     PlayerInfoRequest, // Get saved player data from file (if available)
     PlayerInfoData,    // Data about a player to save, sent at a fixed interval from the client
-    ChunkRequest,      // Request from the client to send data about a chunk
+    ChunkRequest,      // Request from the client to send data about some chunks of a column
     ChunkUpdate,       // Request from the client to update a chunk
     ChunkContents,     // The contents of a chunk as requested by the client
-                       // TODO: Add server message to client // Send a message from the server to the client
-                       // TODO: Add client command to server // Send a command from the client to the server
+    ServerInfo,        // Accepts a connection and describes the server to the client
+    Disconnect,        // Tells the client why the server is closing the connection
+    ChatMessage,       // A chat line typed by a player
+    ServerMessage,     // Send a message from the server to the client
+    Command,           // Send a command from the client to the server
+    AuthChallenge,     // Asks the client to prove it knows the account password
+    AuthResponse,      // The client's answer to an AuthChallenge
+    BlockChanged,      // A single block in a loaded chunk has changed
+    MultiBlockChange,  // Several blocks in one loaded column have changed
+    UnloadColumns,     // Columns that have left the player's view distance
+    StatusRequest,     // Asks for a StatusResponse without logging in
+    StatusResponse,    // Describes the server to anyone who asks, such as a server list
+    ChunkUpdateAck,    // Tells the client whether one of its ChunkUpdates was applied
 }
 
 impl PacketType {
@@ -21,12 +64,649 @@ impl PacketType {
             4 => PacketType::ChunkRequest,
             5 => PacketType::ChunkUpdate,
             6 => PacketType::ChunkContents,
+            7 => PacketType::ServerInfo,
+            8 => PacketType::Disconnect,
+            9 => PacketType::ChatMessage,
+            10 => PacketType::ServerMessage,
+            11 => PacketType::Command,
+            12 => PacketType::AuthChallenge,
+            13 => PacketType::AuthResponse,
+            14 => PacketType::BlockChanged,
+            15 => PacketType::MultiBlockChange,
+            16 => PacketType::UnloadColumns,
+            17 => PacketType::StatusRequest,
+            18 => PacketType::StatusResponse,
+            19 => PacketType::ChunkUpdateAck,
             _ => return Err(()),
         })
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChunkUpdateType {
     PlaceBlockEvent,
     DestroyBlockEvent,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerConnect {
+    /// Must stay the first field so mismatched versions can still be read
+    pub protocol_version: u32,
+    /// Bit set of optional protocol features supported by the client
+    pub capabilities: u32,
+    pub username: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// Sent by a client that is about to leave
+pub struct PlayerDisconnect {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// Asks for the saved data of the player bound to the connection
+pub struct PlayerInfoRequest {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerInfoData {
+    /// Ignored by the server, which always updates the player bound to the connection
+    pub username: String,
+    pub position: Vector3<f32>,
+    pub rotation: Vector2<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkRequest {
+    pub column_position: Vector2<i32>,
+    /// Lowest chunk in the column to send
+    pub min_height: u8,
+    /// Highest chunk in the column to send, inclusive
+    pub max_height: u8,
+}
+
+impl ChunkRequest {
+    /// Requests every chunk of the column at `column_position`
+    pub fn column(column_position: Vector2<i32>) -> ChunkRequest {
+        ChunkRequest {
+            column_position,
+            min_height: 0,
+            max_height: COLUMN_HEIGHT - 1,
+        }
+    }
+
+    /// Requests the single chunk at `chunk_position`
+    pub fn chunk(chunk_position: Vector3<i32>) -> ChunkRequest {
+        let height = chunk_position.y.clamp(0, COLUMN_HEIGHT as i32 - 1) as u8;
+        ChunkRequest {
+            column_position: Vector2::new(chunk_position.x, chunk_position.z),
+            min_height: height,
+            max_height: height,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkUpdate {
+    pub block_position: Vector3<i32>,
+    pub update_type: ChunkUpdateType,
+    /// Id of the block to place, ignored when destroying
+    pub block_id: i32,
+    /// Chosen by the client and echoed in the `ChunkUpdateAck` for this update
+    pub sequence: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChunkUpdateOutcome {
+    Applied,
+    /// Nothing was changed, the client should undo what it predicted
+    Rejected {
+        reason: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkUpdateAck {
+    /// `ChunkUpdate::sequence` of the update
+    pub sequence: u32,
+    pub outcome: ChunkUpdateOutcome,
+}
+
+/// The blocks of a single chunk
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChunkBlocks {
+    /// Every block has the same id, such as a chunk of only air
+    Uniform(i32),
+    /// Run-length encoded blocks, see `Chunk::compress`
+    RunLength(Vec<CompressedSet>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkData {
+    pub position: Vector3<i32>,
+    pub blocks: ChunkBlocks,
+}
+
+impl ChunkData {
+    pub fn from_chunk(chunk: &Chunk) -> ChunkData {
+        let mut sets = chunk.compress();
+        let blocks = match sets.len() {
+            1 => ChunkBlocks::Uniform(sets.remove(0).id),
+            _ => ChunkBlocks::RunLength(sets),
+        };
+
+        ChunkData {
+            position: chunk.position,
+            blocks,
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        match &self.blocks {
+            ChunkBlocks::Uniform(id) => Ok(Chunk::new(self.position, *id)),
+            ChunkBlocks::RunLength(sets) => Chunk::decompress(self.position, sets),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkContents {
+    pub chunks: Vec<ChunkData>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    pub protocol_version: u32,
+    /// Bit set of optional protocol features enabled for this connection
+    pub capabilities: u32,
+    /// Width of a chunk in blocks
+    pub chunk_width: u8,
+    /// Height of a chunk column in chunks
+    pub column_height: u8,
+}
+
+/// Why the server closed a connection, also passed as the enet disconnect data
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectCode {
+    /// The server is shutting down
+    ServerStopping,
+    /// The player was removed by an operator or for flooding the server
+    Kicked,
+    /// The client sent something the protocol does not allow, or uses a different version
+    ProtocolError,
+    /// The server refused the login, such as for a wrong password or a name already in use
+    LoginRefused,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Disconnect {
+    pub code: DisconnectCode,
+    /// Shown to the player
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    /// A chat line relayed from a player
+    Chat,
+    /// A notice generated by the server itself
+    System,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServerMessage {
+    pub kind: MessageKind,
+    /// Name of the player who sent the message, `None` for system messages
+    pub sender: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Command {
+    /// The command line without its leading `/`
+    pub command: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthChallenge {
+    /// Salt the account password was hashed with
+    pub salt: [u8; SALT_LENGTH],
+    /// Random bytes that must be mixed into the response
+    pub nonce: [u8; NONCE_LENGTH],
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthResponse {
+    /// See `auth::challenge_response`
+    pub proof: Hash,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockChanged {
+    /// Position of the block in the world
+    pub position: Vector3<i32>,
+    pub block_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MultiBlockChange {
+    pub changes: Vec<BlockChanged>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UnloadColumns {
+    /// The client should drop every chunk of these columns, the server stops sending changes to
+    /// them
+    pub column_positions: Vec<Vector2<i32>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// May be sent instead of `PlayerConnect`, the server answers and closes the connection
+pub struct StatusRequest {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatusResponse {
+    /// Must stay the first field so clients using other versions can tell they are incompatible
+    pub protocol_version: u32,
+    /// Message of the day
+    pub motd: String,
+    pub world_name: String,
+    /// Players logged in right now
+    pub players: u32,
+    pub max_players: u32,
+    /// Seconds since the server started
+    pub uptime: u64,
+}
+
+/// Set in the type byte of packets with an LZ4 compressed payload
+const COMPRESSED_FLAG: u8 = 0x80;
+/// Largest payload a compressed packet may expand to, guards against decompression bombs
+const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// An encoded packet along with the size of its payload before compression
+pub struct EncodedPacket {
+    pub data: Vec<u8>,
+    pub uncompressed_size: usize,
+}
+
+/// A decoded packet, with one payload per `PacketType`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    PlayerConnect(PlayerConnect),
+    PlayerDisconnect(PlayerDisconnect),
+    PlayerInfoRequest(PlayerInfoRequest),
+    PlayerInfoData(PlayerInfoData),
+    ChunkRequest(ChunkRequest),
+    ChunkUpdate(ChunkUpdate),
+    ChunkContents(ChunkContents),
+    ServerInfo(ServerInfo),
+    Disconnect(Disconnect),
+    ChatMessage(ChatMessage),
+    ServerMessage(ServerMessage),
+    Command(Command),
+    AuthChallenge(AuthChallenge),
+    AuthResponse(AuthResponse),
+    BlockChanged(BlockChanged),
+    MultiBlockChange(MultiBlockChange),
+    UnloadColumns(UnloadColumns),
+    StatusRequest(StatusRequest),
+    StatusResponse(StatusResponse),
+    ChunkUpdateAck(ChunkUpdateAck),
+}
+
+impl Packet {
+    pub fn packet_type(&self) -> PacketType {
+        match self {
+            Packet::PlayerConnect(_) => PacketType::PlayerConnect,
+            Packet::PlayerDisconnect(_) => PacketType::PlayerDisconnect,
+            Packet::PlayerInfoRequest(_) => PacketType::PlayerInfoRequest,
+            Packet::PlayerInfoData(_) => PacketType::PlayerInfoData,
+            Packet::ChunkRequest(_) => PacketType::ChunkRequest,
+            Packet::ChunkUpdate(_) => PacketType::ChunkUpdate,
+            Packet::ChunkContents(_) => PacketType::ChunkContents,
+            Packet::ServerInfo(_) => PacketType::ServerInfo,
+            Packet::Disconnect(_) => PacketType::Disconnect,
+            Packet::ChatMessage(_) => PacketType::ChatMessage,
+            Packet::ServerMessage(_) => PacketType::ServerMessage,
+            Packet::Command(_) => PacketType::Command,
+            Packet::AuthChallenge(_) => PacketType::AuthChallenge,
+            Packet::AuthResponse(_) => PacketType::AuthResponse,
+            Packet::BlockChanged(_) => PacketType::BlockChanged,
+            Packet::MultiBlockChange(_) => PacketType::MultiBlockChange,
+            Packet::UnloadColumns(_) => PacketType::UnloadColumns,
+            Packet::StatusRequest(_) => PacketType::StatusRequest,
+            Packet::StatusResponse(_) => PacketType::StatusResponse,
+            Packet::ChunkUpdateAck(_) => PacketType::ChunkUpdateAck,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        match self {
+            Packet::ChunkRequest(_)
+            | Packet::ChunkUpdate(_)
+            | Packet::ChunkContents(_)
+            | Packet::BlockChanged(_)
+            | Packet::MultiBlockChange(_)
+            | Packet::UnloadColumns(_)
+            | Packet::ChunkUpdateAck(_) => Channel::Chunks,
+            Packet::PlayerConnect(_)
+            | Packet::PlayerDisconnect(_)
+            | Packet::PlayerInfoRequest(_)
+            | Packet::PlayerInfoData(_)
+            | Packet::ServerInfo(_)
+            | Packet::Disconnect(_)
+            | Packet::ChatMessage(_)
+            | Packet::ServerMessage(_)
+            | Packet::Command(_)
+            | Packet::AuthChallenge(_)
+            | Packet::AuthResponse(_)
+            | Packet::StatusRequest(_)
+            | Packet::StatusResponse(_) => Channel::Control,
+        }
+    }
+
+    /// Encodes the packet as `[0: Type][1-: bincode payload]`
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_with(false).data
+    }
+
+    /// Encodes the packet, compressing the payload if `compress` is set and it is large enough.
+    /// Compressed packets have `COMPRESSED_FLAG` set in the type and a size prefixed LZ4 payload
+    pub fn encode_with(&self, compress: bool) -> EncodedPacket {
+        let payload = self.encode_payload();
+        let uncompressed_size = payload.len();
+
+        if compress && uncompressed_size >= COMPRESSION_THRESHOLD {
+            let compressed = lz4_flex::compress_prepend_size(&payload);
+            if compressed.len() < uncompressed_size {
+                let mut data = vec![self.packet_type() as u8 | COMPRESSED_FLAG];
+                data.extend_from_slice(&compressed);
+                return EncodedPacket {
+                    data,
+                    uncompressed_size,
+                };
+            }
+        }
+
+        let mut data = vec![self.packet_type() as u8];
+        data.extend_from_slice(&payload);
+        EncodedPacket {
+            data,
+            uncompressed_size,
+        }
+    }
+
+    fn encode_payload(&self) -> Vec<u8> {
+        let payload = match self {
+            Packet::PlayerConnect(p) => bincode::serialize(p),
+            Packet::PlayerDisconnect(p) => bincode::serialize(p),
+            Packet::PlayerInfoRequest(p) => bincode::serialize(p),
+            Packet::PlayerInfoData(p) => bincode::serialize(p),
+            Packet::ChunkRequest(p) => bincode::serialize(p),
+            Packet::ChunkUpdate(p) => bincode::serialize(p),
+            Packet::ChunkContents(p) => bincode::serialize(p),
+            Packet::ServerInfo(p) => bincode::serialize(p),
+            Packet::Disconnect(p) => bincode::serialize(p),
+            Packet::ChatMessage(p) => bincode::serialize(p),
+            Packet::ServerMessage(p) => bincode::serialize(p),
+            Packet::Command(p) => bincode::serialize(p),
+            Packet::AuthChallenge(p) => bincode::serialize(p),
+            Packet::AuthResponse(p) => bincode::serialize(p),
+            Packet::BlockChanged(p) => bincode::serialize(p),
+            Packet::MultiBlockChange(p) => bincode::serialize(p),
+            Packet::UnloadColumns(p) => bincode::serialize(p),
+            Packet::StatusRequest(p) => bincode::serialize(p),
+            Packet::StatusResponse(p) => bincode::serialize(p),
+            Packet::ChunkUpdateAck(p) => bincode::serialize(p),
+        };
+
+        payload.expect("Packet payloads are always serializable")
+    }
+
+    /// Decodes a packet previously created with `Packet::encode`
+    pub fn decode(data: &[u8]) -> Result<Packet, DecodeError> {
+        let (&type_id, payload) = data.split_first().ok_or(DecodeError::Empty)?;
+        let packet_type = PacketType::fromu8(type_id & !COMPRESSED_FLAG)
+            .map_err(|_| DecodeError::UnknownType(type_id))?;
+
+        let decompressed;
+        let payload = if type_id & COMPRESSED_FLAG != 0 {
+            decompressed = decompress_payload(payload)?;
+            decompressed.as_slice()
+        } else {
+            payload
+        };
+
+        let packet = match packet_type {
+            PacketType::PlayerConnect => decode_payload(payload).map(Packet::PlayerConnect),
+            PacketType::PlayerDisconnect => decode_payload(payload).map(Packet::PlayerDisconnect),
+            PacketType::PlayerInfoRequest => decode_payload(payload).map(Packet::PlayerInfoRequest),
+            PacketType::PlayerInfoData => decode_payload(payload).map(Packet::PlayerInfoData),
+            PacketType::ChunkRequest => decode_payload(payload).map(Packet::ChunkRequest),
+            PacketType::ChunkUpdate => decode_payload(payload).map(Packet::ChunkUpdate),
+            PacketType::ChunkContents => decode_payload(payload).map(Packet::ChunkContents),
+            PacketType::ServerInfo => decode_payload(payload).map(Packet::ServerInfo),
+            PacketType::Disconnect => decode_payload(payload).map(Packet::Disconnect),
+            PacketType::ChatMessage => decode_payload(payload).map(Packet::ChatMessage),
+            PacketType::ServerMessage => decode_payload(payload).map(Packet::ServerMessage),
+            PacketType::Command => decode_payload(payload).map(Packet::Command),
+            PacketType::AuthChallenge => decode_payload(payload).map(Packet::AuthChallenge),
+            PacketType::AuthResponse => decode_payload(payload).map(Packet::AuthResponse),
+            PacketType::BlockChanged => decode_payload(payload).map(Packet::BlockChanged),
+            PacketType::MultiBlockChange => decode_payload(payload).map(Packet::MultiBlockChange),
+            PacketType::UnloadColumns => decode_payload(payload).map(Packet::UnloadColumns),
+            PacketType::StatusRequest => decode_payload(payload).map(Packet::StatusRequest),
+            PacketType::StatusResponse => decode_payload(payload).map(Packet::StatusResponse),
+            PacketType::ChunkUpdateAck => decode_payload(payload).map(Packet::ChunkUpdateAck),
+        };
+
+        packet.map_err(|error| DecodeError::Payload { packet_type, error })
+    }
+}
+
+/// Why `Packet::decode` rejected some bytes
+#[derive(Debug)]
+pub enum DecodeError {
+    Empty,
+    /// The first byte does not name a packet type, compression flag included
+    UnknownType(u8),
+    MissingCompressedSize,
+    /// The compressed payload claims to expand past `MAX_DECOMPRESSED_SIZE`
+    CompressedTooLarge(usize),
+    Decompression(lz4_flex::block::DecompressError),
+    /// The payload does not hold a valid packet of the type it claims to be
+    Payload {
+        packet_type: PacketType,
+        error: bincode::Error,
+    },
+}
+
+impl DecodeError {
+    /// The type the packet claimed to be, if it got far enough to tell
+    pub fn packet_type(&self) -> Option<PacketType> {
+        match self {
+            DecodeError::Payload { packet_type, .. } => Some(*packet_type),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "Received an empty packet"),
+            DecodeError::UnknownType(id) => write!(f, "Unknown packet type with id {}", id),
+            DecodeError::MissingCompressedSize => {
+                write!(f, "Compressed payload is missing its size")
+            }
+            DecodeError::CompressedTooLarge(size) => {
+                write!(f, "Compressed payload claims to expand to {} bytes", size)
+            }
+            DecodeError::Decompression(e) => write!(f, "Unable to decompress payload: {}", e),
+            DecodeError::Payload { packet_type, error } => {
+                write!(
+                    f,
+                    "Invalid \"PacketType::{:?}\" payload: {}",
+                    packet_type, error
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn decompress_payload(payload: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let size = match payload.get(..4) {
+        Some(size) => u32::from_le_bytes(size.try_into().unwrap()) as usize,
+        None => return Err(DecodeError::MissingCompressedSize),
+    };
+    if size > MAX_DECOMPRESSED_SIZE {
+        return Err(DecodeError::CompressedTooLarge(size));
+    }
+
+    lz4_flex::decompress_size_prepended(payload).map_err(DecodeError::Decompression)
+}
+
+fn decode_payload<T: DeserializeOwned>(payload: &[u8]) -> bincode::Result<T> {
+    bincode::deserialize(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_round_trip() {
+        let packets = [
+            Packet::PlayerConnect(PlayerConnect {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: 0,
+                username: "ethan".to_string(),
+            }),
+            Packet::PlayerInfoData(PlayerInfoData {
+                username: "ethan".to_string(),
+                position: Vector3::new(1.0, 80.0, -3.5),
+                rotation: Vector2::new(0.25, -1.0),
+            }),
+            Packet::ChunkUpdate(ChunkUpdate {
+                block_position: Vector3::new(-17, 64, 3),
+                update_type: ChunkUpdateType::PlaceBlockEvent,
+                block_id: 4,
+                sequence: 7,
+            }),
+            Packet::ChunkUpdateAck(ChunkUpdateAck {
+                sequence: 7,
+                outcome: ChunkUpdateOutcome::Rejected {
+                    reason: "Cannot place block over id 1 @ -17,64,3".to_string(),
+                },
+            }),
+            Packet::ServerMessage(ServerMessage {
+                kind: MessageKind::System,
+                sender: None,
+                message: "ethan joined the game".to_string(),
+            }),
+            Packet::ChunkContents(ChunkContents {
+                chunks: vec![
+                    ChunkData {
+                        position: Vector3::new(0, 1, 0),
+                        blocks: ChunkBlocks::Uniform(0),
+                    },
+                    ChunkData {
+                        position: Vector3::new(0, 2, 0),
+                        blocks: ChunkBlocks::RunLength(vec![
+                            CompressedSet { id: 1, count: 2048 },
+                            CompressedSet { id: 0, count: 2048 },
+                        ]),
+                    },
+                ],
+            }),
+            Packet::StatusResponse(StatusResponse {
+                protocol_version: PROTOCOL_VERSION,
+                motd: "Welcome".to_string(),
+                world_name: "world".to_string(),
+                players: 3,
+                max_players: 32,
+                uptime: 3600,
+            }),
+        ];
+
+        for packet in packets {
+            let data = packet.encode();
+            assert_eq!(data[0], packet.packet_type() as u8);
+            assert_eq!(Packet::decode(&data).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn test_uniform_chunk_data() {
+        let mut chunk = Chunk::new(Vector3::new(2, 3, 4), 0);
+        let data = ChunkData::from_chunk(&chunk);
+        assert_eq!(data.blocks, ChunkBlocks::Uniform(0));
+        assert!(
+            Packet::ChunkContents(ChunkContents { chunks: vec![data] })
+                .encode()
+                .len()
+                < 32
+        );
+
+        chunk.set_block(1, 2, 3, 5);
+        let data = ChunkData::from_chunk(&chunk);
+        assert!(matches!(data.blocks, ChunkBlocks::RunLength(_)));
+        assert_eq!(data.to_chunk().unwrap().get_block(1, 2, 3), 5);
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let packet = Packet::ServerMessage(ServerMessage {
+            kind: MessageKind::System,
+            sender: None,
+            message: "a".repeat(COMPRESSION_THRESHOLD * 4),
+        });
+
+        let encoded = packet.encode_with(true);
+        assert_ne!(encoded.data[0] & COMPRESSED_FLAG, 0);
+        assert!(encoded.data.len() < encoded.uncompressed_size);
+        assert_eq!(Packet::decode(&encoded.data).unwrap(), packet);
+
+        // Small payloads are left alone
+        let small = Packet::PlayerInfoRequest(PlayerInfoRequest {});
+        assert_eq!(small.encode_with(true).data, small.encode());
+
+        // Sizes past the limit are rejected before anything is allocated
+        let mut bomb = vec![PacketType::ServerMessage as u8 | COMPRESSED_FLAG];
+        bomb.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Packet::decode(&bomb),
+            Err(DecodeError::CompressedTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_protocol_version_leads_connect_payload() {
+        let packet = Packet::PlayerConnect(PlayerConnect {
+            protocol_version: 0xDEADBEEF,
+            capabilities: 0,
+            username: "ethan".to_string(),
+        });
+        let data = packet.encode();
+        assert_eq!(data[1..5], 0xDEADBEEFu32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_packet_decode_invalid() {
+        assert!(matches!(Packet::decode(&[]), Err(DecodeError::Empty)));
+        assert!(matches!(
+            Packet::decode(&[255]),
+            Err(DecodeError::UnknownType(255))
+        ));
+        let error = Packet::decode(&[PacketType::ChunkRequest as u8, 1, 2]).unwrap_err();
+        assert_eq!(error.packet_type(), Some(PacketType::ChunkRequest));
+        assert!(matches!(
+            Packet::decode(&[PacketType::ChunkContents as u8 | COMPRESSED_FLAG, 1]),
+            Err(DecodeError::MissingCompressedSize)
+        ));
+    }
+}
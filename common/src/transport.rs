@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::packets::Channel;
+
+#[cfg(feature = "enet")]
+pub mod enet;
+#[cfg(feature = "enet")]
+pub mod ip;
+pub mod loopback;
+pub mod tcp;
+
+/// Identifies one connection of a transport, only unique within that transport
+pub type ConnectionId = u32;
+
+/// Something that happened to one of a transport's connections
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransportEvent {
+    /// A connection opened, either one asked for with `Transport::connect` or one from a remote
+    Connected(ConnectionId),
+    /// A connection closed, `data` is what the other end passed to `Transport::disconnect`
+    Disconnected(ConnectionId, u32),
+    /// A packet arrived, still encoded
+    Received(ConnectionId, Vec<u8>),
+}
+
+/// Reliable, ordered delivery of encoded packets between a server and its clients. Servers and
+/// clients only talk through this, so both can run over enet, TCP or in the same process
+pub trait Transport {
+    /// Where a connection can be opened to
+    type Address;
+
+    /// Starts opening a connection to `address`, which is ready to use once its
+    /// `TransportEvent::Connected` arrives
+    fn connect(&mut self, address: &Self::Address) -> Result<ConnectionId>;
+
+    /// Waits up to `timeout` for something to happen to any connection
+    fn service(&mut self, timeout: Duration) -> Result<Option<TransportEvent>>;
+
+    /// Queues `data` to be sent over `connection`, packets on the same channel arrive in order
+    fn send(&mut self, connection: ConnectionId, channel: Channel, data: &[u8]) -> Result<()>;
+
+    /// Closes `connection` once everything queued on it is sent, passing `data` to the other end.
+    /// Both ends get a `TransportEvent::Disconnected` for it
+    fn disconnect(&mut self, connection: ConnectionId, data: u32);
+
+    /// Whether any connection is still open or opening
+    fn has_connections(&mut self) -> bool;
+}
@@ -0,0 +1,123 @@
+use std::net::SocketAddrV4;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use enet::{Address, BandwidthLimit, ChannelLimit, Enet, Event, Host, PacketMode, Peer, PeerState};
+
+use super::{ConnectionId, Transport, TransportEvent};
+use crate::packets::{Channel, CHANNEL_COUNT};
+
+/// A transport over UDP using enet, peers that open fewer than `CHANNEL_COUNT` channels are
+/// refused
+pub struct EnetTransport {
+    host: Host<ConnectionId>,
+    channel_count: usize,
+    next_id: ConnectionId,
+}
+
+impl EnetTransport {
+    /// Creates a host with room for `max_connections` peers of up to `channel_count` channels
+    /// each, listening on `address` if there is one
+    pub fn new(
+        enet: &Enet,
+        address: Option<SocketAddrV4>,
+        max_connections: usize,
+        channel_count: usize,
+    ) -> Result<EnetTransport> {
+        let host = enet.create_host::<ConnectionId>(
+            address.map(Address::from).as_ref(),
+            max_connections,
+            ChannelLimit::Limited(channel_count),
+            BandwidthLimit::Unlimited,
+            BandwidthLimit::Unlimited,
+        )?;
+        Ok(EnetTransport {
+            host,
+            channel_count,
+            next_id: 0,
+        })
+    }
+
+    fn peer(&mut self, connection: ConnectionId) -> Option<Peer<'_, ConnectionId>> {
+        self.host
+            .peers()
+            .find(|peer| peer.data() == Some(&connection))
+    }
+}
+
+impl Transport for EnetTransport {
+    type Address = SocketAddrV4;
+
+    fn connect(&mut self, address: &SocketAddrV4) -> Result<ConnectionId> {
+        let id = self.next_id;
+        let mut peer = self
+            .host
+            .connect(&Address::from(*address), self.channel_count, 0)?;
+        peer.set_data(Some(id));
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    fn service(&mut self, timeout: Duration) -> Result<Option<TransportEvent>> {
+        let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+        // Events for refused peers are swallowed, leaving nothing to report this time
+        let event = match self.host.service(timeout_ms)? {
+            Some(Event::Connect(ref mut peer)) => {
+                if peer.channel_count() < CHANNEL_COUNT {
+                    eprintln!(
+                        "Refused a connection with only {} channels",
+                        peer.channel_count()
+                    );
+                    peer.disconnect(0);
+                    return Ok(None);
+                }
+                // Peers that connected to us have no id yet
+                let id = match peer.data() {
+                    Some(&id) => id,
+                    None => {
+                        let id = self.next_id;
+                        self.next_id += 1;
+                        peer.set_data(Some(id));
+                        id
+                    }
+                };
+                TransportEvent::Connected(id)
+            }
+            Some(Event::Disconnect(ref peer, data)) => match peer.data() {
+                Some(&id) => TransportEvent::Disconnected(id, data),
+                None => return Ok(None),
+            },
+            Some(Event::Receive {
+                ref sender,
+                ref packet,
+                ..
+            }) => match sender.data() {
+                Some(&id) => TransportEvent::Received(id, packet.data().to_vec()),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+
+    fn send(&mut self, connection: ConnectionId, channel: Channel, data: &[u8]) -> Result<()> {
+        let mut peer = self
+            .peer(connection)
+            .filter(|peer| peer.state() == PeerState::Connected)
+            .ok_or_else(|| anyhow!("Connection {} is not open", connection))?;
+        let packet = enet::Packet::new(data, PacketMode::ReliableSequenced)?;
+        Ok(peer.send_packet(packet, channel as u8)?)
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId, data: u32) {
+        if let Some(mut peer) = self.peer(connection) {
+            peer.disconnect_later(data);
+        }
+    }
+
+    fn has_connections(&mut self) -> bool {
+        self.host
+            .peers()
+            .any(|peer| peer.state() != PeerState::Disconnected)
+    }
+}
@@ -0,0 +1,159 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use enet::Enet;
+
+use super::enet::EnetTransport;
+use super::tcp::TcpTransport;
+use super::{ConnectionId, Transport, TransportEvent};
+use crate::packets::Channel;
+
+/// Longest time enet is waited on at once while TCP connections also need polling
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Which transport a connection uses, kept in the lowest bit of its id
+const ENET: ConnectionId = 0;
+const TCP: ConnectionId = 1;
+
+/// A transport over any IP address. enet only supports IPv4, so IPv4 connections go over enet
+/// and IPv6 ones over TCP
+pub struct IpTransport {
+    enet: Option<EnetTransport>,
+    tcp: Option<TcpTransport>,
+}
+
+impl IpTransport {
+    /// Creates a transport that only opens connections, up to `max_connections` over each
+    /// protocol
+    pub fn client(
+        enet: &Enet,
+        max_connections: usize,
+        channel_count: usize,
+    ) -> Result<IpTransport> {
+        Ok(IpTransport {
+            enet: Some(EnetTransport::new(
+                enet,
+                None,
+                max_connections,
+                channel_count,
+            )?),
+            tcp: Some(TcpTransport::new(None, max_connections)?),
+        })
+    }
+
+    /// Creates a transport listening on `address` for up to `max_connections` connections over
+    /// each protocol. The unspecified IPv6 address listens on every IPv4 address as well
+    pub fn listen(
+        enet: &Enet,
+        address: SocketAddr,
+        max_connections: usize,
+        channel_count: usize,
+    ) -> Result<IpTransport> {
+        let enet_address = match address {
+            SocketAddr::V4(v4) => Some(v4),
+            SocketAddr::V6(v6) if v6.ip().is_unspecified() => {
+                Some(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, v6.port()))
+            }
+            SocketAddr::V6(_) => None,
+        };
+        let enet = match enet_address {
+            Some(v4) => Some(EnetTransport::new(
+                enet,
+                Some(v4),
+                max_connections,
+                channel_count,
+            )?),
+            None => None,
+        };
+        let tcp = match address {
+            SocketAddr::V6(_) => Some(TcpTransport::new(Some(address), max_connections)?),
+            SocketAddr::V4(_) => None,
+        };
+        Ok(IpTransport { enet, tcp })
+    }
+}
+
+/// Gives a connection of one of the transports an id unique across both
+fn tag(connection: ConnectionId, protocol: ConnectionId) -> ConnectionId {
+    connection.wrapping_mul(2) | protocol
+}
+
+fn tag_event(event: Option<TransportEvent>, protocol: ConnectionId) -> Option<TransportEvent> {
+    event.map(|event| match event {
+        TransportEvent::Connected(id) => TransportEvent::Connected(tag(id, protocol)),
+        TransportEvent::Disconnected(id, data) => {
+            TransportEvent::Disconnected(tag(id, protocol), data)
+        }
+        TransportEvent::Received(id, data) => TransportEvent::Received(tag(id, protocol), data),
+    })
+}
+
+impl Transport for IpTransport {
+    type Address = SocketAddr;
+
+    fn connect(&mut self, address: &SocketAddr) -> Result<ConnectionId> {
+        match address {
+            SocketAddr::V4(v4) => match &mut self.enet {
+                Some(enet) => Ok(tag(enet.connect(v4)?, ENET)),
+                None => Err(anyhow!("Unable to connect to {} without IPv4", address)),
+            },
+            SocketAddr::V6(_) => match &mut self.tcp {
+                Some(tcp) => Ok(tag(tcp.connect(address)?, TCP)),
+                None => Err(anyhow!("Unable to connect to {} without IPv6", address)),
+            },
+        }
+    }
+
+    fn service(&mut self, timeout: Duration) -> Result<Option<TransportEvent>> {
+        let (enet, tcp) = match (&mut self.enet, &mut self.tcp) {
+            (Some(enet), Some(tcp)) => (enet, tcp),
+            (Some(enet), None) => return Ok(tag_event(enet.service(timeout)?, ENET)),
+            (None, Some(tcp)) => return Ok(tag_event(tcp.service(timeout)?, TCP)),
+            (None, None) => return Ok(None),
+        };
+
+        // Take turns so neither protocol waits on the other
+        let deadline = Instant::now() + timeout;
+        loop {
+            match tcp.service(Duration::ZERO) {
+                Ok(Some(event)) => return Ok(tag_event(Some(event), TCP)),
+                Ok(None) => (),
+                // IPv4 players are still served whatever goes wrong with TCP
+                Err(e) => eprintln!("Unable to service TCP connections: {}", e),
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Some(event) = enet.service(remaining.min(POLL_INTERVAL))? {
+                return Ok(tag_event(Some(event), ENET));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn send(&mut self, connection: ConnectionId, channel: Channel, data: &[u8]) -> Result<()> {
+        let id = connection / 2;
+        match (connection % 2, &mut self.enet, &mut self.tcp) {
+            (ENET, Some(enet), _) => enet.send(id, channel, data),
+            (TCP, _, Some(tcp)) => tcp.send(id, channel, data),
+            _ => Err(anyhow!("Connection {} is not open", connection)),
+        }
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId, data: u32) {
+        let id = connection / 2;
+        match (connection % 2, &mut self.enet, &mut self.tcp) {
+            (ENET, Some(enet), _) => enet.disconnect(id, data),
+            (TCP, _, Some(tcp)) => tcp.disconnect(id, data),
+            _ => (),
+        }
+    }
+
+    fn has_connections(&mut self) -> bool {
+        self.enet
+            .as_mut()
+            .is_some_and(|enet| enet.has_connections())
+            || self.tcp.as_mut().is_some_and(|tcp| tcp.has_connections())
+    }
+}
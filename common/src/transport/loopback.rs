@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use super::{ConnectionId, Transport, TransportEvent};
+use crate::packets::Channel;
+
+/// Lets other loopback transports connect to the one it came from
+#[derive(Clone, Debug)]
+pub struct LoopbackAddress(Sender<Message>);
+
+/// What one loopback transport sends another, ids are the receiver's unless noted otherwise
+#[derive(Debug)]
+enum Message {
+    /// Opens a connection that the sender knows as `id`
+    Connect {
+        reply_to: LoopbackAddress,
+        id: ConnectionId,
+    },
+    /// The connection `id` was opened, the other end knows it as `remote_id`
+    Accepted {
+        id: ConnectionId,
+        remote_id: ConnectionId,
+        reply_to: LoopbackAddress,
+    },
+    Data {
+        id: ConnectionId,
+        data: Vec<u8>,
+    },
+    Disconnect {
+        id: ConnectionId,
+        data: u32,
+    },
+}
+
+/// The other end of a connection
+struct Remote {
+    address: LoopbackAddress,
+    /// Unknown until the other end accepts the connection
+    remote_id: Option<ConnectionId>,
+    /// Set once this end has disconnected, until its own `Disconnected` event is handled
+    closing: bool,
+}
+
+/// A transport whose connections are channels to other loopback transports in the same process,
+/// for tests and for running a server alongside its client
+pub struct LoopbackTransport {
+    inbox: Receiver<Message>,
+    address: LoopbackAddress,
+    connections: HashMap<ConnectionId, Remote>,
+    next_id: ConnectionId,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> LoopbackTransport {
+        let (sender, inbox) = mpsc::channel();
+        LoopbackTransport {
+            inbox,
+            address: LoopbackAddress(sender),
+            connections: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Where other loopback transports can connect to this one
+    pub fn address(&self) -> LoopbackAddress {
+        self.address.clone()
+    }
+
+    fn next_id(&mut self) -> ConnectionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Returns the event `message` causes, if any
+    fn handle(&mut self, message: Message) -> Option<TransportEvent> {
+        match message {
+            Message::Connect {
+                reply_to,
+                id: remote_id,
+            } => {
+                let id = self.next_id();
+                let accepted = Message::Accepted {
+                    id: remote_id,
+                    remote_id: id,
+                    reply_to: self.address(),
+                };
+                // The other end is already gone if it cannot be told
+                reply_to.0.send(accepted).ok()?;
+                self.connections.insert(
+                    id,
+                    Remote {
+                        address: reply_to,
+                        remote_id: Some(remote_id),
+                        closing: false,
+                    },
+                );
+                Some(TransportEvent::Connected(id))
+            }
+            Message::Accepted {
+                id,
+                remote_id,
+                reply_to,
+            } => match self.connections.get_mut(&id) {
+                Some(remote) if !remote.closing => {
+                    remote.remote_id = Some(remote_id);
+                    Some(TransportEvent::Connected(id))
+                }
+                _ => {
+                    // Closed before it was accepted, so the other end has not been told yet
+                    let disconnect = Message::Disconnect {
+                        id: remote_id,
+                        data: 0,
+                    };
+                    reply_to.0.send(disconnect).ok();
+                    None
+                }
+            },
+            Message::Data { id, data } => self
+                .connections
+                .contains_key(&id)
+                .then_some(TransportEvent::Received(id, data)),
+            Message::Disconnect { id, data } => self
+                .connections
+                .remove(&id)
+                .map(|_| TransportEvent::Disconnected(id, data)),
+        }
+    }
+}
+
+impl Default for LoopbackTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    type Address = LoopbackAddress;
+
+    fn connect(&mut self, address: &LoopbackAddress) -> Result<ConnectionId> {
+        let id = self.next_id();
+        let connect = Message::Connect {
+            reply_to: self.address(),
+            id,
+        };
+        address
+            .0
+            .send(connect)
+            .map_err(|_| anyhow!("Nothing is listening at that address anymore"))?;
+        self.connections.insert(
+            id,
+            Remote {
+                address: address.clone(),
+                remote_id: None,
+                closing: false,
+            },
+        );
+        Ok(id)
+    }
+
+    fn service(&mut self, timeout: Duration) -> Result<Option<TransportEvent>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let message = match self.inbox.recv_timeout(remaining) {
+                Ok(message) => message,
+                // The transport holds a sender itself, so the channel is never disconnected
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return Ok(None),
+            };
+            if let Some(event) = self.handle(message) {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    fn send(&mut self, connection: ConnectionId, _channel: Channel, data: &[u8]) -> Result<()> {
+        let remote = self
+            .connections
+            .get(&connection)
+            .filter(|remote| !remote.closing)
+            .ok_or_else(|| anyhow!("Connection {} is not open", connection))?;
+        let remote_id = remote
+            .remote_id
+            .ok_or_else(|| anyhow!("Connection {} has not been accepted yet", connection))?;
+
+        // Everything goes through one queue, so packets on every channel stay in order
+        let message = Message::Data {
+            id: remote_id,
+            data: data.to_vec(),
+        };
+        remote
+            .address
+            .0
+            .send(message)
+            .map_err(|_| anyhow!("Connection {} was closed by the other end", connection))
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId, data: u32) {
+        let remote = match self.connections.get_mut(&connection) {
+            Some(remote) if !remote.closing => remote,
+            _ => return,
+        };
+        remote.closing = true;
+        if let Some(remote_id) = remote.remote_id {
+            let disconnect = Message::Disconnect {
+                id: remote_id,
+                data,
+            };
+            remote.address.0.send(disconnect).ok();
+        }
+        // Both ends hear about the disconnect, as they do with enet
+        let disconnect = Message::Disconnect {
+            id: connection,
+            data,
+        };
+        self.address.0.send(disconnect).ok();
+    }
+
+    fn has_connections(&mut self) -> bool {
+        !self.connections.is_empty()
+    }
+}
+
+impl Drop for LoopbackTransport {
+    /// Closes every connection, as a dropped enet host would once its peers time out
+    fn drop(&mut self) {
+        let connections: Vec<ConnectionId> = self.connections.keys().copied().collect();
+        for connection in connections {
+            self.disconnect(connection, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn test_loopback() {
+        let mut server = LoopbackTransport::new();
+        let mut client = LoopbackTransport::new();
+
+        let to_server = client.connect(&server.address()).unwrap();
+        assert!(client.send(to_server, Channel::Control, b"early").is_err());
+
+        let Some(TransportEvent::Connected(to_client)) = server.service(TIMEOUT).unwrap() else {
+            panic!("The server did not accept the connection");
+        };
+        assert_eq!(
+            client.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Connected(to_server))
+        );
+
+        client.send(to_server, Channel::Control, b"hello").unwrap();
+        client.send(to_server, Channel::Chunks, b"world").unwrap();
+        assert_eq!(
+            server.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Received(to_client, b"hello".to_vec()))
+        );
+        assert_eq!(
+            server.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Received(to_client, b"world".to_vec()))
+        );
+
+        // Packets sent before disconnecting still arrive
+        server.send(to_client, Channel::Control, b"bye").unwrap();
+        server.disconnect(to_client, 3);
+        assert!(server.send(to_client, Channel::Control, b"again").is_err());
+        assert_eq!(
+            server.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Disconnected(to_client, 3))
+        );
+        assert!(!server.has_connections());
+        assert_eq!(
+            client.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Received(to_server, b"bye".to_vec()))
+        );
+        assert_eq!(
+            client.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Disconnected(to_server, 3))
+        );
+        assert_eq!(client.service(Duration::ZERO).unwrap(), None);
+        assert!(client.send(to_server, Channel::Control, b"again").is_err());
+    }
+
+    #[test]
+    fn test_loopback_drop() {
+        let mut server = LoopbackTransport::new();
+        let mut client = LoopbackTransport::new();
+
+        let address = server.address();
+        let to_server = client.connect(&address).unwrap();
+        assert!(matches!(
+            server.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Connected(_))
+        ));
+        client.service(TIMEOUT).unwrap();
+
+        drop(server);
+        assert_eq!(
+            client.service(TIMEOUT).unwrap(),
+            Some(TransportEvent::Disconnected(to_server, 0))
+        );
+        assert!(client.connect(&address).is_err());
+    }
+}
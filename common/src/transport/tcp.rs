@@ -0,0 +1,434 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{ConnectionId, Transport, TransportEvent};
+use crate::packets::Channel;
+
+/// Longest time `connect` waits for the other end to accept
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the sockets are polled while waiting in `service`
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How long a closed connection keeps reading what the other end still sends, so closing it
+/// does not reset the connection before the other end has read the disconnect
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+/// Frames larger than this are taken as a broken connection, the same limit enet uses
+const MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+/// Most bytes queued for a connection before it is taken as not reading and closed, room for a
+/// frame of the largest size on top of what the socket could not take yet
+const MAX_OUTGOING_SIZE: usize = 2 * MAX_FRAME_SIZE;
+/// A frame is its kind, the length of its payload and the payload
+const FRAME_HEADER_SIZE: usize = 5;
+
+const FRAME_DATA: u8 = 0;
+/// Its payload is the `u32` passed to `Transport::disconnect`
+const FRAME_DISCONNECT: u8 = 1;
+
+struct TcpConnection {
+    stream: TcpStream,
+    /// Bytes received that do not make up a whole frame yet
+    incoming: Vec<u8>,
+    /// Frames waiting to be written
+    outgoing: Vec<u8>,
+    /// Set once this end has disconnected, the connection closes once `outgoing` is written
+    closing: Option<u32>,
+}
+
+/// A transport over TCP, used where enet cannot go as it only supports IPv4. Every channel
+/// shares the one stream, so all packets arrive in the order they were sent and, unlike over
+/// enet, control packets wait behind any chunk data sent before them
+pub struct TcpTransport {
+    listener: Option<TcpListener>,
+    /// The last error accepting connections failed with, so one that keeps happening is only
+    /// logged once
+    accept_error: Option<ErrorKind>,
+    max_connections: usize,
+    connections: HashMap<ConnectionId, TcpConnection>,
+    /// Closed connections still being read until the other end closes too
+    lingering: Vec<(TcpStream, Instant)>,
+    events: VecDeque<TransportEvent>,
+    next_id: ConnectionId,
+}
+
+impl TcpTransport {
+    /// Creates a transport with room for `max_connections` connections, listening on `address`
+    /// if there is one
+    pub fn new(address: Option<SocketAddr>, max_connections: usize) -> Result<TcpTransport> {
+        let listener = match address {
+            Some(address) => {
+                let listener = TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+        Ok(TcpTransport {
+            listener,
+            accept_error: None,
+            max_connections,
+            connections: HashMap::new(),
+            lingering: Vec::new(),
+            events: VecDeque::new(),
+            next_id: 0,
+        })
+    }
+
+    /// The address being listened on, which has the actual port if port 0 was asked for
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.listener.as_ref()?.local_addr().ok()
+    }
+
+    fn open(&mut self, stream: TcpStream) -> Result<ConnectionId> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.connections.insert(
+            id,
+            TcpConnection {
+                stream,
+                incoming: Vec::new(),
+                outgoing: Vec::new(),
+                closing: None,
+            },
+        );
+        self.events.push_back(TransportEvent::Connected(id));
+        Ok(id)
+    }
+
+    /// Accepts new connections, writes what is queued and reads what has arrived, queueing an
+    /// event for everything that happened
+    fn poll(&mut self) {
+        while let Some(listener) = &self.listener {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.accept_error = None;
+                    break;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // Such as running out of file descriptors, which connections already open are
+                // not affected by
+                Err(e) => {
+                    if self.accept_error != Some(e.kind()) {
+                        eprintln!("Unable to accept a connection: {}", e);
+                        self.accept_error = Some(e.kind());
+                    }
+                    break;
+                }
+            };
+            self.accept_error = None;
+            if self.connections.len() >= self.max_connections {
+                eprintln!("Refused a connection, there is no room for more");
+                continue;
+            }
+            if let Err(e) = self.open(stream) {
+                eprintln!("Unable to open a connection: {}", e);
+            }
+        }
+
+        let ids: Vec<ConnectionId> = self.connections.keys().copied().collect();
+        for id in ids {
+            if let Some(data) = self.poll_connection(id) {
+                self.close(id, data);
+            }
+        }
+
+        let now = Instant::now();
+        self.lingering
+            .retain_mut(|(stream, deadline)| now < *deadline && discard_incoming(stream));
+    }
+
+    /// Writes and reads what it can for one connection, returning the data to close it with
+    /// if it has closed
+    fn poll_connection(&mut self, id: ConnectionId) -> Option<u32> {
+        let connection = self.connections.get_mut(&id)?;
+        if flush(connection).is_err() {
+            return Some(connection.closing.unwrap_or(0));
+        }
+        if let Some(data) = connection.closing {
+            return connection.outgoing.is_empty().then_some(data);
+        }
+
+        // Frames that arrived before the other end closed are still handled
+        let mut buffer = [0; 16 * 1024];
+        let ended = loop {
+            match connection.stream.read(&mut buffer) {
+                Ok(0) => break true,
+                Ok(read) => connection.incoming.extend_from_slice(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break false,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break true,
+            }
+        };
+
+        let mut start = 0;
+        let mut closed = None;
+        while let Some(header) = connection.incoming.get(start..start + FRAME_HEADER_SIZE) {
+            let kind = header[0];
+            let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            if length > MAX_FRAME_SIZE {
+                eprintln!("Closing a connection that sent a {} byte frame", length);
+                closed = Some(0);
+                break;
+            }
+            let payload_start = start + FRAME_HEADER_SIZE;
+            let payload = match connection
+                .incoming
+                .get(payload_start..payload_start + length)
+            {
+                Some(payload) => payload,
+                None => break,
+            };
+            start = payload_start + length;
+            match (kind, payload) {
+                (FRAME_DATA, _) => self
+                    .events
+                    .push_back(TransportEvent::Received(id, payload.to_vec())),
+                (FRAME_DISCONNECT, &[a, b, c, d]) => {
+                    closed = Some(u32::from_le_bytes([a, b, c, d]));
+                    break;
+                }
+                _ => {
+                    eprintln!("Closing a connection that sent a malformed frame");
+                    closed = Some(0);
+                    break;
+                }
+            }
+        }
+        connection.incoming.drain(..start);
+        closed.or(ended.then_some(0))
+    }
+
+    /// Closes the connection and reports it closed with `data`
+    fn close(&mut self, id: ConnectionId, data: u32) {
+        if let Some(connection) = self.connections.remove(&id) {
+            let _ = connection.stream.shutdown(Shutdown::Write);
+            self.lingering
+                .push((connection.stream, Instant::now() + LINGER_TIMEOUT));
+            self.events
+                .push_back(TransportEvent::Disconnected(id, data));
+        }
+    }
+
+    /// Queues a frame to be written, closing the connection if too much is already waiting to be
+    fn queue_frame(&mut self, id: ConnectionId, kind: u8, payload: &[u8]) -> Result<()> {
+        let length = u32::try_from(payload.len())
+            .ok()
+            .filter(|&length| length as usize <= MAX_FRAME_SIZE)
+            .ok_or_else(|| anyhow!("A {} byte packet is too large to send", payload.len()))?;
+        let connection = self
+            .connections
+            .get_mut(&id)
+            .filter(|open| open.closing.is_none())
+            .ok_or_else(|| anyhow!("Connection {} is not open", id))?;
+        if connection.outgoing.len() + FRAME_HEADER_SIZE + payload.len() > MAX_OUTGOING_SIZE {
+            eprintln!("Closing a connection that is not reading what is sent to it");
+            self.close(id, 0);
+            bail!("Connection {} was closed as it fell too far behind", id);
+        }
+        connection.outgoing.push(kind);
+        connection.outgoing.extend_from_slice(&length.to_le_bytes());
+        connection.outgoing.extend_from_slice(payload);
+        Ok(())
+    }
+}
+
+/// Reads and throws away whatever has arrived on `stream`, returning whether more may come
+fn discard_incoming(stream: &mut TcpStream) -> bool {
+    let mut buffer = [0; 1024];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return false,
+            Ok(_) => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Writes as much of what is queued on `connection` as the socket takes without blocking
+fn flush(connection: &mut TcpConnection) -> std::io::Result<()> {
+    let mut written = 0;
+    let mut result = Ok(());
+    while written < connection.outgoing.len() {
+        match connection.stream.write(&connection.outgoing[written..]) {
+            Ok(0) => {
+                result = Err(ErrorKind::WriteZero.into());
+                break;
+            }
+            Ok(count) => written += count,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    connection.outgoing.drain(..written);
+    result
+}
+
+impl Transport for TcpTransport {
+    type Address = SocketAddr;
+
+    /// Unlike enet, this waits until the other end accepts the connection
+    fn connect(&mut self, address: &SocketAddr) -> Result<ConnectionId> {
+        if self.connections.len() >= self.max_connections {
+            bail!("There is no room for more connections");
+        }
+        let stream = TcpStream::connect_timeout(address, CONNECT_TIMEOUT)?;
+        self.open(stream)
+    }
+
+    fn service(&mut self, timeout: Duration) -> Result<Option<TransportEvent>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.poll();
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            thread::sleep(remaining.min(POLL_INTERVAL));
+        }
+    }
+
+    fn send(&mut self, connection: ConnectionId, _channel: Channel, data: &[u8]) -> Result<()> {
+        self.queue_frame(connection, FRAME_DATA, data)?;
+        // Anything that cannot be written now is written when the transport is next serviced
+        if let Some(open) = self.connections.get_mut(&connection) {
+            let _ = flush(open);
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId, data: u32) {
+        if self
+            .queue_frame(connection, FRAME_DISCONNECT, &data.to_le_bytes())
+            .is_ok()
+        {
+            if let Some(open) = self.connections.get_mut(&connection) {
+                open.closing = Some(data);
+            }
+        }
+    }
+
+    fn has_connections(&mut self) -> bool {
+        !self.connections.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv6Addr, SocketAddrV6};
+
+    /// Services `transport` until it has an event, failing if none arrives in time
+    fn next_event(transport: &mut TcpTransport) -> TransportEvent {
+        transport
+            .service(Duration::from_secs(5))
+            .unwrap()
+            .expect("Expected an event")
+    }
+
+    #[test]
+    fn test_tcp_round_trip() {
+        let localhost = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0));
+        let mut server = match TcpTransport::new(Some(localhost), 1) {
+            Ok(server) => server,
+            // Not every machine the tests run on has IPv6
+            Err(_) => return,
+        };
+        let address = server.local_address().unwrap();
+        let mut client = TcpTransport::new(None, 1).unwrap();
+
+        let server_id = client.connect(&address).unwrap();
+        assert_eq!(
+            next_event(&mut client),
+            TransportEvent::Connected(server_id)
+        );
+        let client_id = match next_event(&mut server) {
+            TransportEvent::Connected(id) => id,
+            other => panic!("Expected a connection, got {:?}", other),
+        };
+
+        // Packets arrive whole and in order, however large
+        let large = vec![7; 100_000];
+        client.send(server_id, Channel::Chunks, &[1, 2, 3]).unwrap();
+        client.send(server_id, Channel::Chunks, &large).unwrap();
+        assert_eq!(
+            next_event(&mut server),
+            TransportEvent::Received(client_id, vec![1, 2, 3])
+        );
+        assert_eq!(
+            next_event(&mut server),
+            TransportEvent::Received(client_id, large)
+        );
+
+        // A second connection is refused while the first is open
+        let mut extra = TcpTransport::new(None, 1).unwrap();
+        let extra_id = extra.connect(&address).unwrap();
+        assert_eq!(next_event(&mut extra), TransportEvent::Connected(extra_id));
+        assert_eq!(server.service(Duration::from_millis(50)).unwrap(), None);
+        assert_eq!(
+            next_event(&mut extra),
+            TransportEvent::Disconnected(extra_id, 0)
+        );
+
+        // Both ends hear about the disconnect, with its data
+        server.send(client_id, Channel::Chunks, &[4]).unwrap();
+        server.disconnect(client_id, 42);
+        assert!(server.send(client_id, Channel::Chunks, &[5]).is_err());
+        assert_eq!(
+            next_event(&mut server),
+            TransportEvent::Disconnected(client_id, 42)
+        );
+        assert!(!server.has_connections());
+        assert_eq!(
+            next_event(&mut client),
+            TransportEvent::Received(server_id, vec![4])
+        );
+        assert_eq!(
+            next_event(&mut client),
+            TransportEvent::Disconnected(server_id, 42)
+        );
+        assert!(!client.has_connections());
+    }
+
+    #[test]
+    fn test_peer_that_stops_reading() {
+        let localhost = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0));
+        let mut server = match TcpTransport::new(Some(localhost), 1) {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+        let mut client = TcpTransport::new(None, 1).unwrap();
+        client.connect(&server.local_address().unwrap()).unwrap();
+        let client_id = match next_event(&mut server) {
+            TransportEvent::Connected(id) => id,
+            other => panic!("Expected a connection, got {:?}", other),
+        };
+
+        // The client is never serviced, so what is sent to it piles up until it is dropped
+        let packet = vec![0; 1024 * 1024];
+        let sent = (0..2 * MAX_OUTGOING_SIZE / packet.len())
+            .take_while(|_| server.send(client_id, Channel::Chunks, &packet).is_ok())
+            .count();
+        assert!(sent * packet.len() >= MAX_OUTGOING_SIZE - packet.len());
+        assert!(sent * packet.len() < 2 * MAX_OUTGOING_SIZE);
+        assert_eq!(
+            next_event(&mut server),
+            TransportEvent::Disconnected(client_id, 0)
+        );
+        assert!(!server.has_connections());
+    }
+}
@@ -0,0 +1,50 @@
+//! Feeds arbitrary packets to the server's dispatcher, run with
+//! `cargo +nightly fuzz run packet_dispatcher` from the `server` directory.
+//!
+//! Each input is split into packets of `[length: u8][bytes]`. They are sent by a fresh peer that
+//! has already connected as a player, so the fuzzer can reach every handler.
+#![no_main]
+
+use std::cell::RefCell;
+
+use common::packets::{Packet, PlayerConnect, PROTOCOL_VERSION};
+use libfuzzer_sys::fuzz_target;
+use server::game::{Game, GameOptions};
+use server::save_file::SaveFile;
+
+thread_local! {
+    /// Loading the scripts is slow, so every input is run against the same game
+    static GAME: RefCell<Option<Game>> = const { RefCell::new(None) };
+}
+
+fn new_game() -> Game {
+    // The default scripts are in the repository root
+    let save = SaveFile::with_scripts_root(None, concat!(env!("CARGO_MANIFEST_DIR"), "/../.."));
+    Game::new(GameOptions::new(), save)
+}
+
+fuzz_target!(|data: &[u8]| {
+    GAME.with(|game| {
+        let mut game = game.borrow_mut();
+        let game = game.get_or_insert_with(new_game);
+
+        let peer_id = game.connect();
+        let connect = Packet::PlayerConnect(PlayerConnect {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: 0,
+            username: "fuzzer".to_string(),
+        });
+        game.handle_packet(peer_id, &connect.encode());
+
+        let mut rest = data;
+        while let Some((&length, tail)) = rest.split_first() {
+            let length = (length as usize).min(tail.len());
+            let (packet, tail) = tail.split_at(length);
+            game.handle_packet(peer_id, packet);
+            rest = tail;
+        }
+
+        game.disconnect(peer_id);
+        game.take_outgoing();
+    });
+});
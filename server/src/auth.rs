@@ -0,0 +1,110 @@
+use common::auth::{
+    client_key, hash_password, stored_key, verify_response, Hash, NONCE_LENGTH, SALT_LENGTH,
+};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Length of the tokens generated by `generate_token`
+const TOKEN_LENGTH: usize = 24;
+
+/// Stored credentials of a player. Neither the password nor anything that can log in as the
+/// player is saved, see `common::auth::stored_key`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Account {
+    salt: [u8; SALT_LENGTH],
+    stored_key: Hash,
+}
+
+/// Credentials as saves from before stored keys kept them, the hash of the password itself
+#[derive(Serialize, Deserialize)]
+pub struct LegacyAccount {
+    salt: [u8; SALT_LENGTH],
+    password_hash: Hash,
+}
+
+impl Account {
+    /// Creates an account with a fresh random salt
+    pub fn new(password: &str) -> Account {
+        let salt = rand::random();
+        Account::from_password_hash(salt, &hash_password(&salt, password))
+    }
+
+    fn from_password_hash(salt: [u8; SALT_LENGTH], password_hash: &Hash) -> Account {
+        Account {
+            salt,
+            stored_key: stored_key(&client_key(password_hash)),
+        }
+    }
+
+    pub fn salt(&self) -> &[u8; SALT_LENGTH] {
+        &self.salt
+    }
+
+    /// Checks a client's answer to the challenge that used `nonce`
+    pub fn verify(&self, nonce: &[u8; NONCE_LENGTH], proof: &Hash) -> bool {
+        verify_response(&self.stored_key, nonce, proof)
+    }
+}
+
+impl From<LegacyAccount> for Account {
+    /// The same password keeps working
+    fn from(legacy: LegacyAccount) -> Account {
+        Account::from_password_hash(legacy.salt, &legacy.password_hash)
+    }
+}
+
+/// A challenge sent to a peer that has not answered yet
+pub struct PendingAuth {
+    pub username: String,
+    pub capabilities: u32,
+    pub nonce: [u8; NONCE_LENGTH],
+}
+
+impl PendingAuth {
+    pub fn new(username: String, capabilities: u32) -> PendingAuth {
+        PendingAuth {
+            username,
+            capabilities,
+            nonce: rand::random(),
+        }
+    }
+}
+
+/// Generates a random password for players to use as a login token
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::auth::challenge_response;
+
+    #[test]
+    fn test_account_verify() {
+        let account = Account::new("hunter2");
+        let nonce = [3; NONCE_LENGTH];
+
+        let proof = challenge_response(&hash_password(account.salt(), "hunter2"), &nonce);
+        assert!(account.verify(&nonce, &proof));
+
+        let wrong = challenge_response(&hash_password(account.salt(), "hunter3"), &nonce);
+        assert!(!account.verify(&nonce, &wrong));
+        assert!(!account.verify(&[4; NONCE_LENGTH], &proof));
+
+        // Accounts saved before stored keys still log in with the same password
+        let salt = [5; SALT_LENGTH];
+        let legacy = LegacyAccount {
+            salt,
+            password_hash: hash_password(&salt, "hunter2"),
+        };
+        let account = Account::from(legacy);
+        let proof = challenge_response(&hash_password(&salt, "hunter2"), &nonce);
+        assert!(account.verify(&nonce, &proof));
+    }
+}
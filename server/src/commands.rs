@@ -0,0 +1,527 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use cgmath::Vector3;
+use common::is_valid_username;
+use common::packets::Packet;
+
+use crate::auth::{generate_token, Account};
+use crate::packets::{assemble_system_message, compression_report};
+use crate::rate_limit::rate_limit_report;
+use crate::world::World;
+
+/// Result of a command, sent back to whoever ran it
+pub type CommandResult = Result<String, String>;
+
+type CommandHandler = fn(&mut CommandContext, &[&str]) -> CommandResult;
+
+/// Who issued a command
+pub enum CommandSender {
+    /// The server's own stdin
+    Console,
+    Player(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Player,
+    Operator,
+}
+
+/// Something only the game can do, carried out once the command finishes
+#[derive(Debug, PartialEq)]
+pub enum CommandRequest {
+    Kick {
+        username: String,
+        reason: String,
+    },
+    /// Moves an online player and streams the columns around where they end up
+    Teleport {
+        username: String,
+        position: Vector3<f32>,
+    },
+    /// Changes a block and tells the players that have its chunk loaded
+    SetBlock {
+        position: Vector3<i32>,
+        block_id: i32,
+    },
+    /// Stops the server at the end of the current tick
+    Stop,
+}
+
+/// Everything a command may read or change while it runs
+pub struct CommandContext<'a> {
+    pub sender: &'a CommandSender,
+    pub world: &'a mut World,
+    pub online_players: &'a [String],
+    /// Packets to send to every connected client once the command finishes
+    pub broadcasts: &'a mut Vec<Packet>,
+    pub requests: &'a mut Vec<CommandRequest>,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub permission: Permission,
+    handler: CommandHandler,
+}
+
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Command>,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        CommandRegistry::new()
+    }
+}
+
+impl CommandRegistry {
+    /// Creates a registry containing the built-in commands
+    pub fn new() -> CommandRegistry {
+        let mut registry = CommandRegistry {
+            commands: BTreeMap::new(),
+        };
+
+        registry.register(Command {
+            name: "list",
+            usage: "list",
+            help: "Lists the players that are online",
+            permission: Permission::Player,
+            handler: list_command,
+        });
+        registry.register(Command {
+            name: "tp",
+            usage: "tp <x> <y> <z> [player]",
+            help: "Teleports a player to a position",
+            permission: Permission::Operator,
+            handler: teleport_command,
+        });
+        registry.register(Command {
+            name: "time",
+            usage: "time [set <ticks>]",
+            help: "Shows or sets the time of day",
+            permission: Permission::Operator,
+            handler: time_command,
+        });
+        registry.register(Command {
+            name: "give",
+            usage: "give <item> [count] [player]",
+            help: "Gives items to a player",
+            permission: Permission::Operator,
+            handler: give_command,
+        });
+        registry.register(Command {
+            name: "save",
+            usage: "save",
+            help: "Writes the world to disk",
+            permission: Permission::Operator,
+            handler: save_command,
+        });
+        registry.register(Command {
+            name: "stop",
+            usage: "stop",
+            help: "Saves the world and stops the server",
+            permission: Permission::Operator,
+            handler: stop_command,
+        });
+        registry.register(Command {
+            name: "kick",
+            usage: "kick <player> [reason]",
+            help: "Disconnects a player",
+            permission: Permission::Operator,
+            handler: kick_command,
+        });
+        registry.register(Command {
+            name: "setblock",
+            usage: "setblock <x> <y> <z> <block>",
+            help: "Changes the block at a position to a block given by name or id",
+            permission: Permission::Operator,
+            handler: set_block_command,
+        });
+        registry.register(Command {
+            name: "say",
+            usage: "say <message>",
+            help: "Sends a message to every player",
+            permission: Permission::Operator,
+            handler: say_command,
+        });
+        registry.register(Command {
+            name: "op",
+            usage: "op <player>",
+            help: "Grants operator permissions to a player",
+            permission: Permission::Operator,
+            handler: |ctx, args| set_operator(ctx, args, true),
+        });
+        registry.register(Command {
+            name: "deop",
+            usage: "deop <player>",
+            help: "Revokes operator permissions from a player",
+            permission: Permission::Operator,
+            handler: |ctx, args| set_operator(ctx, args, false),
+        });
+        registry.register(Command {
+            name: "stats",
+            usage: "stats",
+            help: "Shows how much network traffic compression has saved and how often peers were rate limited",
+            permission: Permission::Operator,
+            handler: |_, _| Ok(format!("{}\n{}", compression_report(), rate_limit_report())),
+        });
+        registry.register(Command {
+            name: "account",
+            usage: "account <set|token|remove> <player> [password]",
+            help: "Creates, generates a login token for or removes a player's account",
+            permission: Permission::Operator,
+            handler: account_command,
+        });
+        registry.register(Command {
+            name: "password",
+            usage: "password <new password>",
+            help: "Sets the password of your own account, which is sent to the server unencrypted",
+            permission: Permission::Player,
+            handler: password_command,
+        });
+
+        registry
+    }
+
+    pub fn register(&mut self, command: Command) {
+        self.commands.insert(command.name, command);
+    }
+
+    /// Parses and runs `line`, with or without a leading `/`
+    pub fn execute(&self, ctx: &mut CommandContext, line: &str) -> CommandResult {
+        let mut words = line.trim().trim_start_matches('/').split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Err("No command given, try \"help\"".to_string()),
+        };
+        let args: Vec<&str> = words.collect();
+
+        let permission = sender_permission(ctx);
+        if name == "help" {
+            return self.help(permission, &args);
+        }
+
+        let command = self
+            .commands
+            .get(name)
+            .ok_or_else(|| format!("Unknown command \"{}\", try \"help\"", name))?;
+
+        if permission < command.permission {
+            return Err(format!("You do not have permission to use \"{}\"", name));
+        }
+
+        (command.handler)(ctx, &args).map_err(|e| format!("{}\nUsage: {}", e, command.usage))
+    }
+
+    fn help(&self, permission: Permission, args: &[&str]) -> CommandResult {
+        match args.first() {
+            Some(name) => match self.commands.get(name) {
+                Some(command) => Ok(format!("{} - {}", command.usage, command.help)),
+                None => Err(format!("Unknown command \"{}\"", name)),
+            },
+            None => {
+                let mut lines =
+                    vec!["help [command] - Lists commands or describes one".to_string()];
+                for command in self.commands.values() {
+                    if command.permission <= permission {
+                        lines.push(format!("{} - {}", command.usage, command.help));
+                    }
+                }
+                Ok(lines.join("\n"))
+            }
+        }
+    }
+}
+
+/// Written in place of passwords when commands are recorded
+const REDACTED: &str = "<redacted>";
+
+/// Returns `line` with any password it sets replaced, so it can be written to disk
+pub fn redact_credentials(line: &str) -> String {
+    let words: Vec<&str> = line
+        .trim()
+        .trim_start_matches('/')
+        .split_whitespace()
+        .collect();
+    // Words before the password, for the commands that take one
+    let kept = match words.as_slice() {
+        ["password", ..] => 1,
+        ["account", "set", ..] => 3,
+        _ => return line.to_string(),
+    };
+    if words.len() <= kept {
+        return line.to_string();
+    }
+    let mut redacted = words[..kept].to_vec();
+    redacted.push(REDACTED);
+    redacted.join(" ")
+}
+
+fn sender_permission(ctx: &mut CommandContext) -> Permission {
+    match ctx.sender {
+        CommandSender::Console => Permission::Operator,
+        CommandSender::Player(username) => {
+            if ctx.world.get_save_file().is_operator(username) {
+                Permission::Operator
+            } else {
+                Permission::Player
+            }
+        }
+    }
+}
+
+/// Parses the argument at `index`, naming it `name` in errors
+fn parse_argument<T: FromStr>(args: &[&str], index: usize, name: &str) -> Result<T, String> {
+    let arg = args
+        .get(index)
+        .ok_or_else(|| format!("Missing argument <{}>", name))?;
+    arg.parse()
+        .map_err(|_| format!("Invalid value \"{}\" for <{}>", arg, name))
+}
+
+/// Resolves the optional player argument at `index`, defaulting to the sender
+fn target_player(ctx: &CommandContext, args: &[&str], index: usize) -> Result<String, String> {
+    let username = match (args.get(index), ctx.sender) {
+        (Some(username), _) => username.to_string(),
+        (None, CommandSender::Player(username)) => username.clone(),
+        (None, CommandSender::Console) => return Err("Missing argument [player]".to_string()),
+    };
+
+    if !ctx.online_players.contains(&username) {
+        return Err(format!("Player {} is not online", username));
+    }
+
+    Ok(username)
+}
+
+fn list_command(ctx: &mut CommandContext, _: &[&str]) -> CommandResult {
+    Ok(format!(
+        "{} player(s) online: {}",
+        ctx.online_players.len(),
+        ctx.online_players.join(", ")
+    ))
+}
+
+fn teleport_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    let position = Vector3::new(
+        parse_argument::<f32>(args, 0, "x")?,
+        parse_argument::<f32>(args, 1, "y")?,
+        parse_argument::<f32>(args, 2, "z")?,
+    );
+    let username = target_player(ctx, args, 3)?;
+    if !(position.x.is_finite() && position.y.is_finite() && position.z.is_finite()) {
+        return Err("The position must be finite".to_string());
+    }
+
+    ctx.requests.push(CommandRequest::Teleport {
+        username: username.clone(),
+        position,
+    });
+
+    Ok(format!(
+        "Teleported {} to {}, {}, {}",
+        username, position.x, position.y, position.z
+    ))
+}
+
+fn time_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    match args.first() {
+        None => Ok(format!(
+            "The time is {}",
+            ctx.world.get_save_file().world_time
+        )),
+        Some(&"set") => {
+            let time = parse_argument::<u32>(args, 1, "ticks")?;
+            ctx.world.get_save_file().world_time = time;
+            Ok(format!("Set the time to {}", time))
+        }
+        Some(arg) => Err(format!("Unknown time action \"{}\"", arg)),
+    }
+}
+
+fn give_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    let item_name = parse_argument::<String>(args, 0, "item")?;
+    let count = match args.get(1) {
+        Some(_) => parse_argument::<u32>(args, 1, "count")?,
+        None => 1,
+    };
+    let username = target_player(ctx, args, 2)?;
+
+    let id = ctx
+        .world
+        .get_item_manager()
+        .get_id_by_name(item_name.clone())
+        .ok_or_else(|| format!("Unknown item \"{}\"", item_name))?;
+    ctx.world
+        .get_save_file()
+        .get_user_data_mut(&username)
+        .give_item(id, count);
+
+    Ok(format!("Gave {} {} to {}", count, item_name, username))
+}
+
+fn save_command(ctx: &mut CommandContext, _: &[&str]) -> CommandResult {
+    ctx.world.flush_save()?;
+    Ok("Saved the world".to_string())
+}
+
+fn stop_command(ctx: &mut CommandContext, _: &[&str]) -> CommandResult {
+    ctx.requests.push(CommandRequest::Stop);
+    Ok("Stopping the server".to_string())
+}
+
+fn kick_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    let username = parse_argument::<String>(args, 0, "player")?;
+    if !ctx.online_players.contains(&username) {
+        return Err(format!("Player {} is not online", username));
+    }
+    let reason = match args.len() {
+        1 => "Kicked by an operator".to_string(),
+        _ => args[1..].join(" "),
+    };
+
+    ctx.requests.push(CommandRequest::Kick {
+        username: username.clone(),
+        reason: reason.clone(),
+    });
+    Ok(format!("Kicked {}: {}", username, reason))
+}
+
+fn set_block_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    let position = Vector3::new(
+        parse_argument::<i32>(args, 0, "x")?,
+        parse_argument::<i32>(args, 1, "y")?,
+        parse_argument::<i32>(args, 2, "z")?,
+    );
+    let block = parse_argument::<String>(args, 3, "block")?;
+
+    if !World::is_block_in_bounds(&position) {
+        return Err(format!(
+            "{}, {}, {} is outside of the world",
+            position.x, position.y, position.z
+        ));
+    }
+    let items = ctx.world.get_item_manager();
+    let block_id = match block.parse::<i32>() {
+        Ok(id) if items.get_item_by_id(id).is_some() => id,
+        Ok(id) => return Err(format!("Unknown block id {}", id)),
+        Err(_) => items
+            .get_id_by_name(block.clone())
+            .ok_or_else(|| format!("Unknown block \"{}\"", block))?,
+    };
+
+    ctx.requests
+        .push(CommandRequest::SetBlock { position, block_id });
+    Ok(format!(
+        "Set the block @ {},{},{} to {}",
+        position.x, position.y, position.z, block
+    ))
+}
+
+fn say_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    if args.is_empty() {
+        return Err("Missing argument <message>".to_string());
+    }
+    let name = match ctx.sender {
+        CommandSender::Console => "Server",
+        CommandSender::Player(username) => username,
+    };
+    let message = format!("[{}] {}", name, args.join(" "));
+
+    ctx.broadcasts.push(assemble_system_message(&message));
+    Ok(message)
+}
+
+fn set_operator(ctx: &mut CommandContext, args: &[&str], is_operator: bool) -> CommandResult {
+    let username = parse_argument::<String>(args, 0, "player")?;
+    ctx.world
+        .get_save_file()
+        .set_operator(&username, is_operator);
+
+    Ok(match is_operator {
+        true => format!("Made {} an operator", username),
+        false => format!("Made {} a regular player", username),
+    })
+}
+
+fn account_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    let action = parse_argument::<String>(args, 0, "set|token|remove")?;
+    let username = parse_argument::<String>(args, 1, "player")?;
+    if !is_valid_username(&username) {
+        return Err(format!("{} is not a valid username", username));
+    }
+    let save_file = ctx.world.get_save_file();
+
+    match action.as_str() {
+        "set" => {
+            let password = parse_argument::<String>(args, 2, "password")?;
+            save_file.set_account(&username, Account::new(&password));
+            Ok(format!("Set the password of {}", username))
+        }
+        "token" => {
+            let token = generate_token();
+            save_file.set_account(&username, Account::new(&token));
+            Ok(format!("Login token for {}: {}", username, token))
+        }
+        "remove" => match save_file.remove_account(&username) {
+            true => Ok(format!("Removed the account of {}", username)),
+            false => Err(format!("{} does not have an account", username)),
+        },
+        _ => Err(format!("Unknown account action \"{}\"", action)),
+    }
+}
+
+/// The new password arrives as typed in the command, unencrypted, unlike when logging in
+fn password_command(ctx: &mut CommandContext, args: &[&str]) -> CommandResult {
+    let username = match ctx.sender {
+        CommandSender::Player(username) => username,
+        CommandSender::Console => {
+            return Err("The console has no account, use \"account set\" instead".to_string())
+        }
+    };
+    let password = parse_argument::<String>(args, 0, "new password")?;
+
+    ctx.world
+        .get_save_file()
+        .set_account(username, Account::new(&password));
+
+    Ok("Your password has been changed".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_credentials() {
+        assert_eq!(
+            redact_credentials("/password hunter2"),
+            "password <redacted>"
+        );
+        assert_eq!(
+            redact_credentials("account set ethan hunter2"),
+            "account set ethan <redacted>"
+        );
+        assert_eq!(redact_credentials("account set ethan"), "account set ethan");
+        assert_eq!(redact_credentials("say my password"), "say my password");
+    }
+
+    #[test]
+    fn test_parse_argument() {
+        let args = ["12", "-3.5", "stone"];
+
+        assert_eq!(parse_argument::<i32>(&args, 0, "x"), Ok(12));
+        assert_eq!(parse_argument::<f32>(&args, 1, "y"), Ok(-3.5));
+        assert_eq!(
+            parse_argument::<f32>(&args, 2, "z"),
+            Err("Invalid value \"stone\" for <z>".to_string())
+        );
+        assert_eq!(
+            parse_argument::<u32>(&args, 3, "count"),
+            Err("Missing argument <count>".to_string())
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::path::Path;
+use std::{env, fs};
+
+use anyhow::{anyhow, Result};
+use common::cli::flag_value;
+use common::net::DEFAULT_PORT;
+use common::packets::CHANNEL_COUNT;
+use serde::{Deserialize, Serialize};
+
+use crate::console_println;
+use crate::game::{GameOptions, MAX_VIEW_DISTANCE};
+use crate::rate_limit::RateLimits;
+
+/// Where the config is read from unless `--config` is given
+pub const DEFAULT_CONFIG_PATH: &str = "./server.toml";
+/// Written above the settings when the config file is created
+const CONFIG_HEADER: &str =
+    "# Server settings, see the README for what each does. Command line flags override them\n\n";
+
+/// Settings read from the config file, missing ones take their default values
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub network: NetworkConfig,
+    pub world: WorldConfig,
+    pub rules: RulesConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// IP address or hostname to listen on, may include a port
+    pub bind: String,
+    /// Used when `bind` does not include a port
+    pub port: u16,
+    pub max_players: usize,
+    /// Channels allocated for each connection, at least `CHANNEL_COUNT`
+    pub channels: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldConfig {
+    pub save_directory: String,
+    /// Replaces the seed of the save, new saves get a random one if this is not set
+    pub seed: Option<i32>,
+    pub name: String,
+    pub motd: String,
+    pub view_distance: u32,
+    /// Seconds between autosaves, 0 to only save when stopping
+    pub autosave_interval: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub require_auth: bool,
+    pub reach: f32,
+    pub max_speed: f32,
+    pub max_packets_per_second: u32,
+    pub max_generations_per_second: u32,
+    pub max_chunk_requests: usize,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            bind: "0.0.0.0".to_string(),
+            port: DEFAULT_PORT,
+            max_players: GameOptions::new().max_players,
+            channels: CHANNEL_COUNT,
+        }
+    }
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        let options = GameOptions::new();
+        WorldConfig {
+            save_directory: "./save".to_string(),
+            seed: None,
+            name: options.world_name,
+            motd: options.motd,
+            view_distance: options.view_distance,
+            autosave_interval: options.autosave_interval,
+        }
+    }
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        let options = GameOptions::new();
+        RulesConfig {
+            require_auth: options.require_auth,
+            reach: options.reach,
+            max_speed: options.max_speed,
+            max_packets_per_second: options.limits.packets_per_second,
+            max_generations_per_second: options.limits.column_generations_per_second,
+            max_chunk_requests: options.limits.max_outstanding_chunk_requests,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the config file at `path`, creating it with the default settings if there is none
+    pub fn load_or_create(path: &str) -> Result<ServerConfig> {
+        if !Path::new(path).exists() {
+            let config = ServerConfig::default();
+            let contents = format!("{}{}", CONFIG_HEADER, toml::to_string(&config)?);
+            fs::write(path, contents)
+                .map_err(|e| anyhow!("Unable to create config file {}: {}", path, e))?;
+            console_println!("Created {} with the default settings", path);
+            return Ok(config);
+        }
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read config file {}: {}", path, e))?;
+        ServerConfig::parse(&contents).map_err(|e| anyhow!("Invalid config file {}: {}", path, e))
+    }
+
+    /// Parses and validates the contents of a config file
+    pub fn parse(contents: &str) -> Result<ServerConfig> {
+        let config: ServerConfig = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Applies the command line flags, which override the file. The result still has to be
+    /// validated
+    pub fn parse_cli(self) -> ServerConfig {
+        let args: Vec<String> = env::args().collect();
+        self.apply_flags(&args)
+    }
+
+    fn apply_flags(mut self, args: &[String]) -> ServerConfig {
+        let network = &mut self.network;
+        if let Some(value) = flag_value(args, "--bind") {
+            network.bind = value;
+        }
+        if let Some(value) = flag_value(args, "--port") {
+            network.port = value;
+        }
+        if let Some(value) = flag_value(args, "--max_players") {
+            network.max_players = value;
+        }
+        if let Some(value) = flag_value(args, "--channels") {
+            network.channels = value;
+        }
+
+        let world = &mut self.world;
+        if let Some(value) = flag_value(args, "--save_directory") {
+            world.save_directory = value;
+        }
+        if let Some(value) = flag_value(args, "--seed") {
+            world.seed = Some(value);
+        }
+        if let Some(value) = flag_value(args, "--world_name") {
+            world.name = value;
+        }
+        if let Some(value) = flag_value(args, "--motd") {
+            world.motd = value;
+        }
+        if let Some(value) = flag_value(args, "--view_distance") {
+            world.view_distance = value;
+        }
+        if let Some(value) = flag_value(args, "--autosave_interval") {
+            world.autosave_interval = value;
+        }
+
+        let rules = &mut self.rules;
+        // Only turns authentication on, so it stays on when the config file requires it
+        if args.iter().any(|arg| arg == "--require_auth") {
+            rules.require_auth = true;
+        }
+        if let Some(value) = flag_value(args, "--reach") {
+            rules.reach = value;
+        }
+        if let Some(value) = flag_value(args, "--max_speed") {
+            rules.max_speed = value;
+        }
+        if let Some(value) = flag_value(args, "--max_packets_per_second") {
+            rules.max_packets_per_second = value;
+        }
+        if let Some(value) = flag_value(args, "--max_generations_per_second") {
+            rules.max_generations_per_second = value;
+        }
+        if let Some(value) = flag_value(args, "--max_chunk_requests") {
+            rules.max_chunk_requests = value;
+        }
+
+        self
+    }
+
+    /// Checks every setting, listing all that are out of range
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut check = |valid: bool, problem: String| {
+            if !valid {
+                problems.push(problem);
+            }
+        };
+
+        let network = &self.network;
+        check(
+            !network.bind.is_empty(),
+            "network.bind must not be empty".to_string(),
+        );
+        check(
+            network.max_players > 0,
+            "network.max_players must be at least 1".to_string(),
+        );
+        check(
+            network.channels >= CHANNEL_COUNT,
+            format!(
+                "network.channels must be at least {}, but is {}",
+                CHANNEL_COUNT, network.channels
+            ),
+        );
+
+        let world = &self.world;
+        check(
+            !world.save_directory.is_empty()
+                && !world.save_directory.contains('\\')
+                && !world.save_directory.ends_with('/'),
+            format!(
+                "world.save_directory must be a path without \\ characters or a trailing /, but is \"{}\"",
+                world.save_directory
+            ),
+        );
+        check(
+            world.view_distance <= MAX_VIEW_DISTANCE,
+            format!(
+                "world.view_distance must be at most {}, but is {}",
+                MAX_VIEW_DISTANCE, world.view_distance
+            ),
+        );
+
+        let rules = &self.rules;
+        for (name, value) in [("reach", rules.reach), ("max_speed", rules.max_speed)] {
+            check(
+                value.is_finite() && value > 0.0,
+                format!("rules.{} must be greater than 0, but is {}", name, value),
+            );
+        }
+        for (name, value) in [
+            (
+                "max_packets_per_second",
+                rules.max_packets_per_second as usize,
+            ),
+            (
+                "max_generations_per_second",
+                rules.max_generations_per_second as usize,
+            ),
+            ("max_chunk_requests", rules.max_chunk_requests),
+        ] {
+            check(value > 0, format!("rules.{} must be at least 1", name));
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!("\n  {}", problems.join("\n  "))),
+        }
+    }
+
+    /// The options the game is run with
+    pub fn game_options(&self) -> GameOptions {
+        let mut options = GameOptions::new();
+        options.max_players = self.network.max_players;
+        options.world_name = self.world.name.clone();
+        options.motd = self.world.motd.clone();
+        options.view_distance = self.world.view_distance;
+        options.autosave_interval = self.world.autosave_interval;
+        options.require_auth = self.rules.require_auth;
+        options.reach = self.rules.reach;
+        options.max_speed = self.rules.max_speed;
+        options.limits = RateLimits {
+            packets_per_second: self.rules.max_packets_per_second,
+            column_generations_per_second: self.rules.max_generations_per_second,
+            max_outstanding_chunk_requests: self.rules.max_chunk_requests,
+        };
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_round_trips() {
+        let config = ServerConfig::default();
+        let contents = toml::to_string(&config).unwrap();
+        assert_eq!(ServerConfig::parse(&contents).unwrap(), config);
+
+        // Settings left out keep their defaults
+        let config = ServerConfig::parse("[world]\nseed = 42\n").unwrap();
+        assert_eq!(config.world.seed, Some(42));
+        assert_eq!(config.network, NetworkConfig::default());
+        assert_eq!(config.game_options().reach, GameOptions::new().reach);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let error = ServerConfig::parse("[rules]\nreach = -1.0\nmax_chunk_requests = 0\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("rules.reach must be greater than 0, but is -1"));
+        assert!(error.contains("rules.max_chunk_requests must be at least 1"));
+
+        // Misspelled settings are pointed out rather than ignored
+        let error = ServerConfig::parse("[network]\nprot = 1234\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("prot"));
+        assert!(ServerConfig::parse("[network]\nport = \"1234\"\n").is_err());
+    }
+
+    #[test]
+    fn test_command_line_flags() {
+        let args =
+            |line: &str| -> Vec<String> { line.split_whitespace().map(str::to_string).collect() };
+
+        let config = ServerConfig::default().apply_flags(&args("server --seed 5 --require_auth"));
+        assert_eq!(config.world.seed, Some(5));
+        assert!(config.rules.require_auth);
+        assert!(config.validate().is_ok());
+
+        // Flags are checked the same way as the file
+        let config = ServerConfig::default().apply_flags(&args(
+            "server --reach -1 --max_packets_per_second 0 --save_directory foo/ --view_distance 100",
+        ));
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("rules.reach must be greater than 0, but is -1"));
+        assert!(error.contains("rules.max_packets_per_second must be at least 1"));
+        assert!(error.contains("world.save_directory must be a path"));
+        assert!(error.contains("world.view_distance must be at most"));
+    }
+}
@@ -0,0 +1,133 @@
+#[cfg(unix)]
+use std::io::Write;
+use std::io::{self, IsTerminal};
+use std::sync::mpsc::{self, Receiver, TryIter};
+use std::sync::Mutex;
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+
+/// Clears the prompt's line and turns off the bracketed paste mode the prompt turns on
+#[cfg(unix)]
+const CLEAR_PROMPT: &str = "\r\x1b[K\x1b[?2004l";
+/// How long the prompt is given to print what is still queued when the console is closed, as
+/// rustyline prints on the reader thread without saying when it is done
+#[cfg(unix)]
+const PRINT_DRAIN_TIME: Duration = Duration::from_millis(50);
+
+/// Prints above the prompt while the console is open, so output does not write over the line
+/// being typed
+static PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+
+/// Prints a line of server output, above the console prompt when there is one
+#[macro_export]
+macro_rules! console_println {
+    ($($arg:tt)*) => {
+        $crate::console::print_line(format!($($arg)*), false)
+    };
+}
+
+/// Prints a line of server errors, above the console prompt when there is one
+#[macro_export]
+macro_rules! console_eprintln {
+    ($($arg:tt)*) => {
+        $crate::console::print_line(format!($($arg)*), true)
+    };
+}
+
+/// Prints `line` through the console prompt when it is open, or to stdout or stderr otherwise
+pub fn print_line(line: String, error: bool) {
+    if let Some(printer) = PRINTER.lock().unwrap().as_mut() {
+        if printer.print(line.clone()).is_ok() {
+            return;
+        }
+    }
+    match error {
+        true => eprintln!("{}", line),
+        false => println!("{}", line),
+    }
+}
+
+/// Reads command lines typed into the server's terminal on a background thread, with line editing
+/// and history when it is a terminal, so reading never holds up ticks. The terminal is put back
+/// how it was when this is dropped
+pub struct Console {
+    lines: Receiver<String>,
+    /// The terminal settings from before the prompt changed them
+    #[cfg(unix)]
+    terminal: Option<Termios>,
+}
+
+impl Console {
+    pub fn open() -> Console {
+        let (sender, lines) = mpsc::channel();
+        let console = Console {
+            lines,
+            #[cfg(unix)]
+            terminal: tcgetattr(io::stdin()).ok(),
+        };
+
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                eprintln!("Unable to open the console: {}", e);
+                return console;
+            }
+        };
+        // Without a terminal there is no prompt to print around
+        if io::stdin().is_terminal() {
+            match editor.create_external_printer() {
+                Ok(printer) => *PRINTER.lock().unwrap() = Some(Box::new(printer)),
+                Err(e) => eprintln!("Unable to print above the console prompt: {}", e),
+            }
+        }
+
+        thread::spawn(move || loop {
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                // The terminal does not send SIGINT while a line is being edited
+                Err(ReadlineError::Interrupted) => "stop".to_string(),
+                // Stdin closed, such as when running in the background
+                Err(_) => break,
+            };
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+            }
+            if sender.send(line).is_err() {
+                break;
+            }
+        });
+
+        console
+    }
+
+    /// The lines typed since this was last called
+    pub fn lines(&self) -> TryIter<'_, String> {
+        self.lines.try_iter()
+    }
+}
+
+impl Drop for Console {
+    /// The reader stays blocked on the prompt until the process exits, so the terminal is
+    /// restored here rather than by the editor
+    fn drop(&mut self) {
+        let printed = PRINTER.lock().unwrap().take().is_some();
+        #[cfg(unix)]
+        if let Some(terminal) = &self.terminal {
+            if printed {
+                thread::sleep(PRINT_DRAIN_TIME);
+            }
+            if tcsetattr(io::stdin(), SetArg::TCSADRAIN, terminal).is_ok() {
+                print!("{}", CLEAR_PROMPT);
+                let _ = io::stdout().flush();
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = printed;
+    }
+}
@@ -0,0 +1,1515 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use cgmath::{Vector2, Vector3};
+use common::{
+    coordinates::{world_to_chunk_position, world_to_column_position},
+    is_valid_username,
+    items::ItemManager,
+    packets::{
+        AuthResponse, BlockChanged, ChatMessage, ChunkRequest, ChunkUpdate, ChunkUpdateOutcome,
+        ChunkUpdateType, Command, Disconnect, DisconnectCode, Packet, PacketType, PlayerConnect,
+        PlayerInfoData, CAPABILITY_LZ4, MAX_CHAT_MESSAGE_LENGTH, PROTOCOL_VERSION,
+        SUPPORTED_CAPABILITIES,
+    },
+    COLUMN_HEIGHT, MAX_FLY_SPEED, MAX_USERNAME_LENGTH,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::PendingAuth;
+use crate::commands::{
+    CommandContext, CommandRegistry, CommandRequest, CommandResult, CommandSender,
+};
+use crate::items::load_items;
+use crate::packets::*;
+use crate::rate_limit::{self, RateLimits, Verdict};
+use crate::replay::{RecordedEvent, Recorder};
+use crate::save_file::SaveFile;
+use crate::session::{PeerId, Sessions};
+use crate::tick::{tick_report, TICKS_PER_SECOND};
+use crate::validation::{has_line_of_sight, within_reach, MoveBudget};
+use crate::world::World;
+use crate::{console_eprintln, console_println};
+
+const DEFAULT_VIEW_DISTANCE: u32 = 4;
+/// Larger view distances would queue more columns than a player could ever be sent
+pub const MAX_VIEW_DISTANCE: u32 = 32;
+/// The client cannot change blocks farther away than this either
+const DEFAULT_REACH: f32 = 8.0;
+/// The client flies no faster than this, even with both of its speed modifiers held
+const DEFAULT_MAX_SPEED: f32 = MAX_FLY_SPEED;
+const DEFAULT_AUTOSAVE_INTERVAL: u32 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GameOptions {
+    pub init_only: bool,
+    /// Reject players that do not have an account
+    pub require_auth: bool,
+    pub limits: RateLimits,
+    /// Distance in columns around each player that is streamed to it
+    pub view_distance: u32,
+    /// The most connections accepted at once, status requests included
+    pub max_players: usize,
+    /// Message of the day, shown in server lists
+    pub motd: String,
+    pub world_name: String,
+    /// Farthest from a player in blocks that it may change blocks
+    pub reach: f32,
+    /// Fastest a player may move in blocks each second
+    pub max_speed: f32,
+    /// Seconds between saves of the chunks and players that changed, 0 to only save when stopping
+    pub autosave_interval: u32,
+}
+
+impl GameOptions {
+    pub fn new() -> Self {
+        GameOptions {
+            init_only: false,
+            require_auth: false,
+            limits: RateLimits::default(),
+            view_distance: DEFAULT_VIEW_DISTANCE,
+            max_players: 32,
+            motd: "A voxelbuilder server".to_string(),
+            world_name: "world".to_string(),
+            reach: DEFAULT_REACH,
+            max_speed: DEFAULT_MAX_SPEED,
+            autosave_interval: DEFAULT_AUTOSAVE_INTERVAL,
+        }
+    }
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        GameOptions::new()
+    }
+}
+
+/// Something the network layer has to do on behalf of the game
+#[derive(Debug, PartialEq)]
+pub enum Outgoing {
+    Send(PeerId, Packet),
+    /// Sends one packet to every listed peer
+    Multicast(Vec<PeerId>, Packet),
+    /// Closes the connection to the peer, telling it why if there is a reason
+    Disconnect(PeerId, Option<Disconnect>),
+}
+
+/// The state of the server, driven by the network layer handing it connections and packets
+pub struct Game {
+    options: GameOptions,
+
+    sessions: Sessions,
+    world: World,
+    commands: CommandRegistry,
+
+    outgoing: Vec<Outgoing>,
+    /// Packets to send to every connected peer, such as those queued by commands
+    broadcasts: Vec<Packet>,
+    /// Blocks changed since the last flush, sent to every peer that has their chunk loaded
+    block_changes: HashMap<Vector3<i32>, Vec<BlockChanged>>,
+
+    recorder: Option<Recorder>,
+    /// Set while replaying, when the recorded times are used instead of the system clock
+    replay_time: Option<Instant>,
+    /// When the event being handled happened
+    now: Instant,
+    /// When the server started, for the uptime in status responses
+    started: Instant,
+    /// Ticks run since the world was last saved
+    ticks_since_save: u32,
+    stop_requested: bool,
+}
+
+impl Game {
+    pub fn new(options: GameOptions, save: SaveFile) -> Game {
+        let mut item_manager = ItemManager::new();
+        load_items(
+            &mut item_manager,
+            save.get_script_path("loadAssetInfo".to_string()),
+        );
+
+        Game {
+            sessions: Sessions::new(options.limits),
+            options,
+            world: World::new(item_manager, save),
+            commands: CommandRegistry::new(),
+            outgoing: Vec::new(),
+            broadcasts: Vec::new(),
+            block_changes: HashMap::new(),
+            recorder: None,
+            replay_time: None,
+            now: Instant::now(),
+            started: Instant::now(),
+            ticks_since_save: 0,
+            stop_requested: false,
+        }
+    }
+
+    pub fn options(&self) -> &GameOptions {
+        &self.options
+    }
+
+    /// Records everything the game is told to do from now on
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Makes the game treat everything it is told from now on as happening at `now`
+    pub fn set_time(&mut self, now: Instant) {
+        self.replay_time = Some(now);
+    }
+
+    /// Opens a session for a peer that has just connected
+    pub fn connect(&mut self) -> PeerId {
+        let now = self.begin();
+        let peer_id = self.sessions.open(now);
+        self.record(|| RecordedEvent::Connect(peer_id));
+        peer_id
+    }
+
+    /// Closes the session of a peer whose connection is gone
+    pub fn disconnect(&mut self, peer_id: PeerId) {
+        self.begin();
+        self.record(|| RecordedEvent::Disconnect(peer_id));
+        let session = self.sessions.close(peer_id);
+        if let Some(username) = session.and_then(|session| session.username) {
+            console_println!("Player {} has left.", username);
+            self.broadcasts.push(assemble_system_message(&format!(
+                "{} left the game",
+                username
+            )));
+        }
+    }
+
+    /// Whether large packets sent to `peer_id` should be compressed
+    pub fn compresses(&self, peer_id: PeerId) -> bool {
+        self.sessions.compresses(peer_id)
+    }
+
+    /// Runs a line typed into the server console
+    pub fn run_console_command(&mut self, line: &str) -> CommandResult {
+        self.begin();
+        self.record(|| RecordedEvent::console(line));
+        self.run_command(CommandSender::Console, line)
+    }
+
+    /// Whether a command has asked for the server to stop
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    fn run_command(&mut self, sender: CommandSender, line: &str) -> CommandResult {
+        let mut requests = Vec::new();
+        let mut ctx = CommandContext {
+            sender: &sender,
+            world: &mut self.world,
+            online_players: &self.sessions.online_players(),
+            broadcasts: &mut self.broadcasts,
+            requests: &mut requests,
+        };
+        let result = self.commands.execute(&mut ctx, line);
+
+        for request in requests {
+            match request {
+                CommandRequest::Kick { username, reason } => {
+                    if let Some(peer_id) = self.sessions.find_player(&username) {
+                        console_println!("Kicked {}: {}", username, reason);
+                        self.kick(peer_id, DisconnectCode::Kicked, &reason);
+                    }
+                }
+                CommandRequest::Teleport { username, position } => {
+                    if let Some(peer_id) = self.sessions.find_player(&username) {
+                        self.teleport(peer_id, &username, position);
+                    }
+                }
+                CommandRequest::SetBlock { position, block_id } => {
+                    self.world.set_block(&position, block_id);
+                    self.queue_block_change(position);
+                }
+                CommandRequest::Stop => self.stop_requested = true,
+            }
+        }
+        result
+    }
+
+    /// Returns everything the network layer has to do since the last call
+    pub fn take_outgoing(&mut self) -> Vec<Outgoing> {
+        for packet in self.broadcasts.drain(..) {
+            self.outgoing
+                .push(Outgoing::Multicast(self.sessions.peers(), packet));
+        }
+        for (chunk_position, changes) in self.block_changes.drain() {
+            self.outgoing.push(Outgoing::Multicast(
+                self.sessions.peers_with_loaded(&chunk_position),
+                assemble_block_changes(changes),
+            ));
+        }
+
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Runs one tick of the world, called `TICKS_PER_SECOND` times each second: advances the time
+    /// of day, answers the chunk requests that were waiting for column generations and autosaves
+    pub fn tick(&mut self) {
+        let now = self.begin();
+        self.record(|| RecordedEvent::Tick);
+        let save = self.world.get_save_file();
+        save.world_time = save.world_time.wrapping_add(1);
+        for peer_id in self.sessions.peers() {
+            self.serve_chunk_requests(peer_id, now);
+        }
+
+        self.ticks_since_save += 1;
+        let autosave_ticks = self
+            .options
+            .autosave_interval
+            .saturating_mul(TICKS_PER_SECOND);
+        if autosave_ticks > 0 && self.ticks_since_save >= autosave_ticks {
+            self.ticks_since_save = 0;
+            self.world.save_to_file();
+        }
+    }
+
+    /// Tells every player the server is stopping and disconnects them, call `shutdown` once they
+    /// have left
+    pub fn stop(&mut self) {
+        // Sent straight away rather than broadcast, broadcasts would go out after the disconnects
+        self.outgoing.push(Outgoing::Multicast(
+            self.sessions.peers(),
+            assemble_system_message("Server stopping"),
+        ));
+        for peer_id in self.sessions.peers() {
+            self.kick(
+                peer_id,
+                DisconnectCode::ServerStopping,
+                "The server is stopping",
+            );
+        }
+    }
+
+    /// Prints the network reports and writes the world to the save file
+    pub fn shutdown(&mut self) {
+        console_println!("{}", compression_report());
+        console_println!("{}", rate_limit::rate_limit_report());
+        console_println!("{}", tick_report());
+        let _ = self.world.flush_save();
+    }
+
+    /// Handles the bytes of one packet from `peer_id`, peers sending malformed packets are
+    /// disconnected
+    pub fn handle_packet(&mut self, peer_id: PeerId, data: &[u8]) {
+        let now = self.begin();
+        self.record(|| RecordedEvent::packet(peer_id, data));
+        let session = match self.sessions.get_mut(peer_id) {
+            Some(session) if !session.closing => session,
+            _ => return,
+        };
+        match session.limiter.check_packet(now) {
+            Verdict::Allow => (),
+            Verdict::Throttle => {
+                // Block changes are still answered so the player's prediction is undone
+                if let Ok(Packet::ChunkUpdate(update)) = Packet::decode(data) {
+                    let outcome = ChunkUpdateOutcome::Rejected {
+                        reason: "Sending packets too quickly".to_string(),
+                    };
+                    self.send(peer_id, assemble_chunk_update_ack(update.sequence, outcome));
+                }
+                return;
+            }
+            Verdict::Kick => {
+                console_println!("Kicking a peer for sending packets too quickly");
+                rate_limit::record_kick();
+                self.kick(
+                    peer_id,
+                    DisconnectCode::Kicked,
+                    "Sending packets too quickly",
+                );
+                return;
+            }
+        }
+
+        let packet = match Packet::decode(data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                console_eprintln!("Unable to decode packet: {}", e);
+                let reason = match e.packet_type() {
+                    Some(PacketType::PlayerConnect) => "Unable to read the connection request, the client may be using a different protocol version".to_string(),
+                    _ => format!("Malformed packet: {}", e),
+                };
+                self.kick(peer_id, DisconnectCode::ProtocolError, &reason);
+                return;
+            }
+        };
+
+        // Every packet except the first PlayerConnect belongs to the bound player
+        let username = match (&packet, self.sessions.username(peer_id)) {
+            (
+                Packet::PlayerConnect(_) | Packet::AuthResponse(_) | Packet::StatusRequest(_),
+                None,
+            ) => String::new(),
+            (Packet::PlayerConnect(_), Some(username)) => {
+                console_eprintln!("Player {} tried to connect a second time", username);
+                return;
+            }
+            (_, Some(username)) => username.to_string(),
+            (_, None) => {
+                console_eprintln!(
+                    "Ignoring \"PacketType::{:?}\" from a peer that has not connected as a player",
+                    packet.packet_type()
+                );
+                return;
+            }
+        };
+
+        match packet {
+            Packet::PlayerConnect(info) => self.handle_player_connect(peer_id, info),
+            Packet::AuthResponse(response) => self.handle_auth_response(peer_id, response),
+            Packet::PlayerDisconnect(_) => self.close_connection(peer_id, None),
+            Packet::ChatMessage(chat) => self.handle_chat_message(&username, chat),
+            Packet::Command(command) => self.handle_command(peer_id, username, command),
+            Packet::PlayerInfoRequest(_) => {
+                let player = self.world.get_save_file().get_user_data(&username);
+                let packet = assemble_player_info_data(player);
+                self.send(peer_id, packet);
+            }
+            Packet::PlayerInfoData(info) => self.handle_player_info(peer_id, &username, info),
+            Packet::ChunkRequest(request) => self.handle_chunk_request(peer_id, request),
+            Packet::ChunkUpdate(update) => self.handle_chunk_update(peer_id, update),
+            Packet::StatusRequest(_) => self.handle_status_request(peer_id),
+            Packet::ChunkContents(_)
+            | Packet::ServerInfo(_)
+            | Packet::AuthChallenge(_)
+            | Packet::BlockChanged(_)
+            | Packet::MultiBlockChange(_)
+            | Packet::UnloadColumns(_)
+            | Packet::Disconnect(_)
+            | Packet::ServerMessage(_)
+            | Packet::StatusResponse(_)
+            | Packet::ChunkUpdateAck(_) => {
+                console_eprintln!(
+                    "Server received \"PacketType::{:?}\". Clients should not be sending this...",
+                    packet.packet_type()
+                );
+                self.kick(
+                    peer_id,
+                    DisconnectCode::ProtocolError,
+                    &format!(
+                        "Clients may not send \"PacketType::{:?}\"",
+                        packet.packet_type()
+                    ),
+                );
+            }
+        }
+    }
+
+    fn send(&mut self, peer_id: PeerId, packet: Packet) {
+        self.outgoing.push(Outgoing::Send(peer_id, packet));
+    }
+
+    /// Tells the peer something it asked for was not valid, without disconnecting it
+    fn send_error(&mut self, peer_id: PeerId, message: &str) {
+        console_eprintln!("{}", message);
+        self.send(peer_id, assemble_system_message(message));
+    }
+
+    /// Notes the time of the event about to be handled
+    fn begin(&mut self) -> Instant {
+        self.now = self.replay_time.unwrap_or_else(Instant::now);
+        self.now
+    }
+
+    fn record(&mut self, event: impl FnOnce() -> RecordedEvent) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(self.now, event());
+        }
+    }
+
+    /// Sends `reason` to the peer and closes its connection
+    fn kick(&mut self, peer_id: PeerId, code: DisconnectCode, reason: &str) {
+        let reason = Disconnect {
+            code,
+            reason: reason.to_string(),
+        };
+        self.close_connection(peer_id, Some(reason));
+    }
+
+    fn close_connection(&mut self, peer_id: PeerId, reason: Option<Disconnect>) {
+        if let Some(session) = self.sessions.get_mut(peer_id) {
+            session.closing = true;
+        }
+        self.outgoing.push(Outgoing::Disconnect(peer_id, reason));
+    }
+
+    fn handle_player_connect(&mut self, peer_id: PeerId, info: PlayerConnect) {
+        if info.protocol_version != PROTOCOL_VERSION {
+            console_println!(
+                "Rejected player {} using protocol version {}",
+                info.username,
+                info.protocol_version
+            );
+            self.kick(
+                peer_id,
+                DisconnectCode::ProtocolError,
+                &format!(
+                    "Protocol version mismatch: the server uses version {} but the client uses version {}",
+                    PROTOCOL_VERSION, info.protocol_version
+                ),
+            );
+            return;
+        }
+
+        if !is_valid_username(&info.username) {
+            self.kick(
+                peer_id,
+                DisconnectCode::LoginRefused,
+                &format!(
+                    "Usernames must be 1 to {} letters, digits or underscores",
+                    MAX_USERNAME_LENGTH
+                ),
+            );
+            return;
+        }
+        if self.sessions.is_online(&info.username) {
+            self.kick(
+                peer_id,
+                DisconnectCode::LoginRefused,
+                &format!("A player named {} is already connected", info.username),
+            );
+            return;
+        }
+
+        match self.world.get_save_file().get_account(&info.username) {
+            Some(account) => {
+                let pending = PendingAuth::new(info.username, info.capabilities);
+                let challenge = assemble_auth_challenge(account, &pending.nonce);
+                self.send(peer_id, challenge);
+                self.sessions.begin_auth(peer_id, pending);
+            }
+            None if self.options.require_auth => {
+                console_println!("Rejected player {} without an account", info.username);
+                self.kick(
+                    peer_id,
+                    DisconnectCode::LoginRefused,
+                    &format!(
+                        "This server requires an account, ask an operator to create one for {}",
+                        info.username
+                    ),
+                );
+            }
+            None => self.accept_player(peer_id, &info.username, info.capabilities),
+        }
+    }
+
+    fn handle_auth_response(&mut self, peer_id: PeerId, response: AuthResponse) {
+        let pending = match self.sessions.take_pending_auth(peer_id) {
+            Some(pending) => pending,
+            None => {
+                console_eprintln!("Received an authentication response that was not asked for");
+                return;
+            }
+        };
+
+        let verified = self
+            .world
+            .get_save_file()
+            .get_account(&pending.username)
+            .is_some_and(|account| account.verify(&pending.nonce, &response.proof));
+        if !verified {
+            console_println!("Player {} failed to authenticate", pending.username);
+            self.kick(peer_id, DisconnectCode::LoginRefused, "Incorrect password");
+            return;
+        }
+        if self.sessions.is_online(&pending.username) {
+            self.kick(
+                peer_id,
+                DisconnectCode::LoginRefused,
+                &format!("A player named {} is already connected", pending.username),
+            );
+            return;
+        }
+
+        self.accept_player(peer_id, &pending.username, pending.capabilities);
+    }
+
+    /// Binds `username` to the peer's session and tells everyone the player joined
+    fn accept_player(&mut self, peer_id: PeerId, username: &str, capabilities: u32) {
+        console_println!("Player {} connected!", username);
+        let compress = capabilities & SUPPORTED_CAPABILITIES & CAPABILITY_LZ4 != 0;
+        self.sessions.bind(peer_id, username, compress);
+        if let Some(session) = self.sessions.get_mut(peer_id) {
+            session.move_budget = Some(MoveBudget::new(self.options.max_speed, self.now));
+        }
+        self.send(peer_id, assemble_server_info(capabilities));
+        self.broadcasts.push(assemble_system_message(&format!(
+            "{} joined the game",
+            username
+        )));
+
+        // Start streaming around where the player was saved, until it reports its position
+        let position = self
+            .world
+            .get_save_file()
+            .get_user_data(&username.to_string())
+            .position;
+        self.update_view(peer_id, column_containing(&position));
+    }
+
+    /// Answers a status request, closing the connection unless a player is logged in on it
+    fn handle_status_request(&mut self, peer_id: PeerId) {
+        let players = self.sessions.online_players().len();
+        let uptime = self.now.saturating_duration_since(self.started);
+        let status = assemble_status_response(&self.options, players, uptime);
+        self.send(peer_id, status);
+        if self.sessions.username(peer_id).is_none() {
+            self.close_connection(peer_id, None);
+        }
+    }
+
+    fn handle_chat_message(&mut self, username: &str, chat: ChatMessage) {
+        let message: String = chat
+            .message
+            .trim()
+            .chars()
+            .take(MAX_CHAT_MESSAGE_LENGTH)
+            .collect();
+        if message.is_empty() {
+            return;
+        }
+
+        console_println!("<{}> {}", username, message);
+        self.broadcasts
+            .push(assemble_chat_message(username, &message));
+    }
+
+    fn handle_command(&mut self, peer_id: PeerId, username: String, command: Command) {
+        console_println!(
+            "{} issued command: /{}",
+            username,
+            command
+                .command
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+        );
+        let output = match self.run_command(CommandSender::Player(username), &command.command) {
+            Ok(output) => output,
+            Err(e) => e,
+        };
+        for line in output.lines() {
+            self.send(peer_id, assemble_system_message(line));
+        }
+    }
+
+    fn handle_chunk_request(&mut self, peer_id: PeerId, request: ChunkRequest) {
+        if request.min_height > request.max_height || request.max_height >= COLUMN_HEIGHT {
+            self.send_error(
+                peer_id,
+                &format!(
+                    "Invalid request for chunks {} to {} of a column",
+                    request.min_height, request.max_height
+                ),
+            );
+            return;
+        }
+        let col_position = request.column_position;
+        if !World::is_column_in_bounds(&col_position) {
+            self.send_error(
+                peer_id,
+                &format!(
+                    "Column {},{} is outside of the world",
+                    col_position.x, col_position.y
+                ),
+            );
+            return;
+        }
+
+        let max_outstanding = self.options.limits.max_outstanding_chunk_requests;
+        let session = match self.sessions.get_mut(peer_id) {
+            Some(session) => session,
+            None => return,
+        };
+        if session.chunk_requests.len() >= max_outstanding {
+            console_println!("Kicking a peer for sending too many chunk requests");
+            rate_limit::record_kick();
+            self.kick(peer_id, DisconnectCode::Kicked, "Too many chunk requests");
+            return;
+        }
+        session.chunk_requests.push_back(request);
+
+        self.serve_chunk_requests(peer_id, self.now);
+    }
+
+    /// Answers the peer's waiting chunk requests in order, until it runs out of column generations
+    fn serve_chunk_requests(&mut self, peer_id: PeerId, now: Instant) {
+        loop {
+            let session = match self.sessions.get_mut(peer_id) {
+                Some(session) if !session.closing => session,
+                _ => return,
+            };
+            // Explicit requests are answered before streamed columns
+            let (col_position, heights, streamed) = match session.chunk_requests.front() {
+                Some(request) => (
+                    request.column_position,
+                    request.min_height..=request.max_height,
+                    false,
+                ),
+                None => match session.column_queue.front() {
+                    Some(&col_position) => (col_position, 0..=COLUMN_HEIGHT - 1, true),
+                    None => return,
+                },
+            };
+
+            if !self.world.does_column_exist(&col_position) {
+                if !session.limiter.try_generate(now) {
+                    rate_limit::record_deferred_generation();
+                    return;
+                }
+                rate_limit::record_generation();
+            }
+            if streamed {
+                session.column_queue.pop_front();
+            } else {
+                session.chunk_requests.pop_front();
+            }
+
+            for height in heights.clone() {
+                let chunk_position = Vector3::new(col_position.x, height as i32, col_position.y);
+                self.sessions.load_chunk(peer_id, chunk_position);
+            }
+            let col = self.world.get_column(&col_position);
+            let packet = assemble_chunk_contents_packet(col, heights);
+            self.send(peer_id, packet);
+        }
+    }
+
+    /// Queues the columns within the view distance of `center` nearest first, and unloads those
+    /// that have moved out of it, including ones the peer requested itself. Columns are only
+    /// unloaded one column past the view distance so walking back and forth over a column border
+    /// does not resend them
+    fn update_view(&mut self, peer_id: PeerId, center: Vector2<i32>) {
+        let view_distance = self.options.view_distance as i64;
+        let session = match self.sessions.get_mut(peer_id) {
+            Some(session) if session.view_center != Some(center) => session,
+            _ => return,
+        };
+        session.view_center = Some(center);
+
+        let outside = |column: &Vector2<i32>| {
+            column_distance_squared(column, &center) > (view_distance + 1).pow(2)
+        };
+        session.streamed_columns.retain(|column| !outside(column));
+        session
+            .column_queue
+            .retain(|column| session.streamed_columns.contains(column));
+        // Only columns with loaded chunks were sent, whether they were streamed or requested
+        let unloaded: HashSet<Vector2<i32>> = session
+            .loaded_chunks
+            .iter()
+            .map(|chunk| Vector2::new(chunk.x, chunk.z))
+            .filter(outside)
+            .collect();
+        session
+            .loaded_chunks
+            .retain(|chunk| !unloaded.contains(&Vector2::new(chunk.x, chunk.z)));
+        let unloaded: Vec<Vector2<i32>> = unloaded.into_iter().collect();
+
+        let radius = view_distance as i32;
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                let column = Vector2::new(center.x.saturating_add(x), center.y.saturating_add(z));
+                if column_distance_squared(&column, &center) > view_distance.pow(2)
+                    || !World::is_column_in_bounds(&column)
+                    || !session.streamed_columns.insert(column)
+                {
+                    continue;
+                }
+                session.column_queue.push_back(column);
+            }
+        }
+        session
+            .column_queue
+            .make_contiguous()
+            .sort_by_key(|column| column_distance_squared(column, &center));
+
+        if !unloaded.is_empty() {
+            self.send(peer_id, assemble_unload_columns(unloaded));
+        }
+        self.serve_chunk_requests(peer_id, self.now);
+    }
+
+    /// Moves the player to where it reports being, or back to where it was if it could not have
+    /// got there
+    fn handle_player_info(&mut self, peer_id: PeerId, username: &str, info: PlayerInfoData) {
+        let now = self.now;
+        let username = username.to_string();
+        let player = self.world.get_save_file().get_user_data(&username);
+        let moved = match self
+            .sessions
+            .get_mut(peer_id)
+            .and_then(|session| session.move_budget.as_mut())
+        {
+            Some(budget) => budget.spend(player.position, info.position, now),
+            None => return,
+        };
+        if let Err(reason) = moved {
+            console_println!("Rejected a move by {}: {}", username, reason);
+            let packet = assemble_player_info_data(player);
+            self.send(peer_id, packet);
+            return;
+        }
+
+        // Players report their position even when standing still, which needs no saving
+        if player.position != info.position || player.rotation != info.rotation {
+            let player = self.world.get_save_file().get_user_data_mut(&username);
+            player.position = info.position;
+            player.rotation = info.rotation;
+        }
+        self.update_view(peer_id, column_containing(&info.position));
+    }
+
+    /// Moves the player to `position`, telling only them, and starts their move budget over from
+    /// there so the jump is not taken for cheating
+    fn teleport(&mut self, peer_id: PeerId, username: &str, position: Vector3<f32>) {
+        let player = self
+            .world
+            .get_save_file()
+            .get_user_data_mut(&username.to_string());
+        player.position = position;
+        let packet = assemble_player_info_data(player);
+        self.send(peer_id, packet);
+
+        if let Some(session) = self.sessions.get_mut(peer_id) {
+            session.move_budget = Some(MoveBudget::new(self.options.max_speed, self.now));
+        }
+        self.update_view(peer_id, column_containing(&position));
+    }
+
+    /// Applies a block change from a player and tells it whether the change was made
+    fn handle_chunk_update(&mut self, peer_id: PeerId, update: ChunkUpdate) {
+        let outcome = match self.apply_chunk_update(peer_id, &update) {
+            Ok(()) => ChunkUpdateOutcome::Applied,
+            Err(reason) => {
+                console_println!("{}", reason);
+                ChunkUpdateOutcome::Rejected { reason }
+            }
+        };
+        self.send(peer_id, assemble_chunk_update_ack(update.sequence, outcome));
+    }
+
+    /// Changes the block if the update is allowed, returning why it is not otherwise
+    fn apply_chunk_update(&mut self, peer_id: PeerId, update: &ChunkUpdate) -> Result<(), String> {
+        let block_pos = update.block_position;
+        if !World::is_block_in_bounds(&block_pos) {
+            return Err(format!(
+                "Cannot change the block @ {},{},{}, it is outside of the world",
+                block_pos.x, block_pos.y, block_pos.z
+            ));
+        }
+        // Only loaded chunks may be changed, so updates cannot be used to generate columns
+        if !self
+            .sessions
+            .has_loaded(peer_id, &world_to_chunk_position(&block_pos))
+        {
+            return Err(format!(
+                "Cannot change the block @ {},{},{}, its chunk is not loaded",
+                block_pos.x, block_pos.y, block_pos.z
+            ));
+        }
+
+        // Players may only change blocks they can reach and see
+        let username = self
+            .sessions
+            .username(peer_id)
+            .unwrap_or_default()
+            .to_string();
+        let eye = self.world.get_save_file().get_user_data(&username).position;
+        if !within_reach(eye, block_pos, self.options.reach) {
+            return Err(format!(
+                "Cannot change the block @ {},{},{}, it is out of {}'s reach",
+                block_pos.x, block_pos.y, block_pos.z, username
+            ));
+        }
+        // Blocks that can be seen through, such as glass and leaves, do not block the view
+        let world = &self.world;
+        let items = world.get_item_manager();
+        if !has_line_of_sight(eye, block_pos, |cell| {
+            world
+                .get_generated_block(&cell)
+                .is_some_and(|id| !items.is_transparent(id).unwrap_or(false))
+        }) {
+            return Err(format!(
+                "Cannot change the block @ {},{},{}, {} cannot see it",
+                block_pos.x, block_pos.y, block_pos.z, username
+            ));
+        }
+
+        let existing_id = self.world.get_block(&block_pos);
+        match update.update_type {
+            ChunkUpdateType::PlaceBlockEvent => {
+                if !self
+                    .world
+                    .get_item_manager()
+                    .is_placeable_block(update.block_id)
+                {
+                    return Err(format!(
+                        "Cannot place id {} @ {},{},{}, it is not a block",
+                        update.block_id, block_pos.x, block_pos.y, block_pos.z
+                    ));
+                }
+                if existing_id > 0 {
+                    return Err(format!(
+                        "Cannot place block over id {} @ {},{},{}",
+                        existing_id, block_pos.x, block_pos.y, block_pos.z
+                    ));
+                }
+                self.world.set_block(&block_pos, update.block_id);
+            }
+            ChunkUpdateType::DestroyBlockEvent => {
+                if existing_id < 1 {
+                    return Err(format!(
+                        "Cannot destroy empty block id {} @ {},{},{}",
+                        existing_id, block_pos.x, block_pos.y, block_pos.z
+                    ));
+                }
+                self.world.set_block(&block_pos, 0);
+            }
+        }
+
+        let block_id = self.world.get_block(&block_pos);
+        if block_id == existing_id {
+            return Err(format!(
+                "The block @ {},{},{} is already id {}",
+                block_pos.x, block_pos.y, block_pos.z, block_id
+            ));
+        }
+        self.queue_block_change(block_pos);
+        Ok(())
+    }
+
+    /// Tells the players that have the chunk of `position` loaded what the block there now is
+    fn queue_block_change(&mut self, position: Vector3<i32>) {
+        let block_id = self.world.get_block(&position);
+        self.block_changes
+            .entry(world_to_chunk_position(&position))
+            .or_default()
+            .push(BlockChanged { position, block_id });
+    }
+}
+
+/// Column holding the block a player at `position` is in
+fn column_containing(position: &Vector3<f32>) -> Vector2<i32> {
+    world_to_column_position(&Vector2::new(
+        position.x.floor() as i32,
+        position.z.floor() as i32,
+    ))
+}
+
+fn column_distance_squared(a: &Vector2<i32>, b: &Vector2<i32>) -> i64 {
+    let x = a.x as i64 - b.x as i64;
+    let z = a.y as i64 - b.y as i64;
+    x * x + z * z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::auth::{challenge_response, hash_password, Hash, SALT_LENGTH};
+    use common::packets::{ChunkUpdateAck, PlayerInfoRequest, StatusRequest};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    const REPOSITORY_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+
+    /// Creates a game without a save directory, loading the scripts from the repository root
+    fn test_game(options: GameOptions) -> Game {
+        Game::new(options, SaveFile::with_scripts_root(None, REPOSITORY_ROOT))
+    }
+
+    fn connect_player(game: &mut Game, username: &str) -> PeerId {
+        let peer_id = game.connect();
+        game.handle_packet(
+            peer_id,
+            &Packet::PlayerConnect(PlayerConnect {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: 0,
+                username: username.to_string(),
+            })
+            .encode(),
+        );
+        game.take_outgoing();
+
+        peer_id
+    }
+
+    #[test]
+    fn test_malformed_packets_disconnect() {
+        let mut game = test_game(GameOptions::new());
+
+        for data in [&[][..], &[PacketType::ChunkRequest as u8, 1]] {
+            let peer_id = game.connect();
+            game.handle_packet(peer_id, data);
+            assert!(matches!(
+                game.take_outgoing().as_slice(),
+                [Outgoing::Disconnect(id, Some(Disconnect { code: DisconnectCode::ProtocolError, .. }))]
+                    if *id == peer_id
+            ));
+
+            // Nothing else from a kicked peer is handled
+            game.handle_packet(peer_id, &[]);
+            assert!(game.take_outgoing().is_empty());
+        }
+
+        // Well formed packets from a peer that has not connected as a player are ignored
+        let peer_id = game.connect();
+        game.handle_packet(
+            peer_id,
+            &Packet::PlayerInfoRequest(PlayerInfoRequest {}).encode(),
+        );
+        assert!(game.take_outgoing().is_empty());
+    }
+
+    #[test]
+    fn test_status_request() {
+        let mut options = GameOptions::new();
+        options.motd = "Welcome".to_string();
+        options.max_players = 8;
+        let mut game = test_game(options);
+        connect_player(&mut game, "ethan");
+
+        let peer_id = game.connect();
+        game.handle_packet(peer_id, &Packet::StatusRequest(StatusRequest {}).encode());
+        match game.take_outgoing().as_slice() {
+            [Outgoing::Send(id, Packet::StatusResponse(status)), Outgoing::Disconnect(closed, None)] =>
+            {
+                assert_eq!((*id, *closed), (peer_id, peer_id));
+                assert_eq!(status.protocol_version, PROTOCOL_VERSION);
+                assert_eq!(status.motd, "Welcome");
+                assert_eq!((status.players, status.max_players), (1, 8));
+            }
+            outgoing => panic!("Expected a status response, got {:?}", outgoing),
+        }
+    }
+
+    #[test]
+    fn test_invalid_usernames() {
+        let mut game = test_game(GameOptions::new());
+
+        for username in ["../x", "", "a/b", "with space", "seventeen_letters"] {
+            let peer_id = game.connect();
+            game.handle_packet(
+                peer_id,
+                &Packet::PlayerConnect(PlayerConnect {
+                    protocol_version: PROTOCOL_VERSION,
+                    capabilities: 0,
+                    username: username.to_string(),
+                })
+                .encode(),
+            );
+            assert!(
+                matches!(
+                    game.take_outgoing().as_slice(),
+                    [Outgoing::Disconnect(id, Some(Disconnect { code: DisconnectCode::LoginRefused, .. }))]
+                        if *id == peer_id
+                ),
+                "{} was not refused",
+                username
+            );
+        }
+
+        connect_player(&mut game, "Valid_name_16chr");
+        assert!(game.sessions.is_online("Valid_name_16chr"));
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut game = test_game(GameOptions::new());
+        let player = connect_player(&mut game, "ethan");
+        let connecting = game.connect();
+
+        game.stop();
+        let outgoing = game.take_outgoing();
+        match outgoing.as_slice() {
+            [Outgoing::Multicast(peers, Packet::ServerMessage(_)), disconnects @ ..] => {
+                assert_eq!(peers.len(), 2);
+                assert_eq!(disconnects.len(), 2);
+            }
+            _ => panic!(
+                "Expected a message followed by disconnects, got {:?}",
+                outgoing
+            ),
+        }
+        for peer_id in [player, connecting] {
+            assert!(outgoing.iter().any(|action| matches!(
+                action,
+                Outgoing::Disconnect(id, Some(Disconnect { code: DisconnectCode::ServerStopping, .. }))
+                    if *id == peer_id
+            )));
+        }
+
+        // Leaving peers can no longer send anything
+        game.handle_packet(
+            player,
+            &Packet::PlayerInfoRequest(PlayerInfoRequest {}).encode(),
+        );
+        assert!(game.take_outgoing().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_request_outside_world() {
+        let mut game = test_game(GameOptions::new());
+        let peer_id = connect_player(&mut game, "ethan");
+
+        let request = ChunkRequest::column(Vector2::new(i32::MIN, 0));
+        game.handle_packet(peer_id, &Packet::ChunkRequest(request).encode());
+        assert!(matches!(
+            game.take_outgoing().as_slice(),
+            [Outgoing::Send(id, Packet::ServerMessage(_))] if *id == peer_id
+        ));
+    }
+
+    #[test]
+    fn test_chunk_update_ack() {
+        let mut game = test_game(GameOptions::new());
+        let peer_id = connect_player(&mut game, "ethan");
+        let request = ChunkRequest::column(Vector2::new(0, 0));
+        game.handle_packet(peer_id, &Packet::ChunkRequest(request).encode());
+        game.tick();
+        game.take_outgoing();
+        // High enough above the ground that nothing is in the way
+        game.world
+            .get_save_file()
+            .get_user_data_mut(&"ethan".to_string())
+            .position = Vector3::new(1.5, 247.5, 1.5);
+
+        let place = |sequence, block_id| {
+            Packet::ChunkUpdate(ChunkUpdate {
+                block_position: Vector3::new(1, 250, 1),
+                update_type: ChunkUpdateType::PlaceBlockEvent,
+                block_id,
+                sequence,
+            })
+            .encode()
+        };
+        game.handle_packet(peer_id, &place(1, 1));
+        match game.take_outgoing().as_slice() {
+            [Outgoing::Send(_, Packet::ChunkUpdateAck(ack)), Outgoing::Multicast(_, Packet::BlockChanged(change))] =>
+            {
+                assert_eq!(ack.sequence, 1);
+                assert_eq!(ack.outcome, ChunkUpdateOutcome::Applied);
+                assert_eq!(change.block_id, 1);
+            }
+            outgoing => panic!("Expected an ack and the change, got {:?}", outgoing),
+        }
+
+        // Placing over the new block is rejected without changing anything
+        game.handle_packet(peer_id, &place(2, 1));
+        match game.take_outgoing().as_slice() {
+            [Outgoing::Send(
+                _,
+                Packet::ChunkUpdateAck(ChunkUpdateAck {
+                    sequence: 2,
+                    outcome: ChunkUpdateOutcome::Rejected { reason },
+                }),
+            )] => assert!(reason.starts_with("Cannot place block over id 1")),
+            outgoing => panic!("Expected a rejection, got {:?}", outgoing),
+        }
+
+        // Only ids of blocks can be placed, not air or ids that do not exist
+        for (sequence, block_id) in [(3, 0), (4, -1), (5, 1000)] {
+            game.handle_packet(peer_id, &place(sequence, block_id));
+            match game.take_outgoing().as_slice() {
+                [Outgoing::Send(
+                    _,
+                    Packet::ChunkUpdateAck(ChunkUpdateAck {
+                        outcome: ChunkUpdateOutcome::Rejected { reason },
+                        ..
+                    }),
+                )] => assert!(reason.ends_with("it is not a block"), "{}", reason),
+                outgoing => panic!("Expected a rejection, got {:?}", outgoing),
+            }
+        }
+        assert_eq!(game.world.get_block(&Vector3::new(1, 250, 1)), 1);
+    }
+
+    #[test]
+    fn test_edit_and_move_validation() {
+        let mut game = test_game(GameOptions::new());
+        let peer_id = connect_player(&mut game, "ethan");
+        let request = ChunkRequest::column(Vector2::new(0, 0));
+        game.handle_packet(peer_id, &Packet::ChunkRequest(request).encode());
+        game.tick();
+        game.take_outgoing();
+        let start = Vector3::new(1.5, 240.5, 1.5);
+        game.world
+            .get_save_file()
+            .get_user_data_mut(&"ethan".to_string())
+            .position = start;
+        game.world.set_block(&Vector3::new(1, 243, 1), 1);
+
+        let rejection = |game: &mut Game, position| {
+            let update = Packet::ChunkUpdate(ChunkUpdate {
+                block_position: position,
+                update_type: ChunkUpdateType::PlaceBlockEvent,
+                block_id: 1,
+                sequence: 0,
+            });
+            game.handle_packet(peer_id, &update.encode());
+            match game.take_outgoing().as_slice() {
+                [Outgoing::Send(_, Packet::ChunkUpdateAck(ack)), ..] => match &ack.outcome {
+                    ChunkUpdateOutcome::Rejected { reason } => Some(reason.clone()),
+                    ChunkUpdateOutcome::Applied => None,
+                },
+                outgoing => panic!("Expected an ack, got {:?}", outgoing),
+            }
+        };
+        assert!(rejection(&mut game, Vector3::new(14, 240, 1))
+            .unwrap()
+            .contains("reach"));
+        // The block placed above the player is in the way
+        assert!(rejection(&mut game, Vector3::new(1, 245, 1))
+            .unwrap()
+            .contains("cannot see"));
+        assert_eq!(rejection(&mut game, Vector3::new(4, 240, 1)), None);
+        // Glass is no more in the way than air
+        let glass = game
+            .world
+            .get_item_manager()
+            .get_id_by_name("Glass".to_string())
+            .unwrap();
+        game.world.set_block(&Vector3::new(1, 243, 1), glass);
+        assert_eq!(rejection(&mut game, Vector3::new(1, 245, 1)), None);
+
+        // Moving a few blocks is fine, jumping across the world is not
+        let move_to = |game: &mut Game, position| {
+            let info = Packet::PlayerInfoData(PlayerInfoData {
+                username: String::new(),
+                position,
+                rotation: Vector2::new(0.0, 0.0),
+            });
+            game.handle_packet(peer_id, &info.encode());
+            game.take_outgoing()
+        };
+        let nearby = start + Vector3::new(3.0, 0.0, 0.0);
+        assert!(move_to(&mut game, nearby).is_empty());
+        match move_to(&mut game, Vector3::new(5000.0, 80.0, 0.0)).as_slice() {
+            [Outgoing::Send(_, Packet::PlayerInfoData(info))] => assert_eq!(info.position, nearby),
+            outgoing => panic!("Expected to be sent back, got {:?}", outgoing),
+        }
+    }
+
+    #[test]
+    fn test_chunk_request_flood() {
+        let mut options = GameOptions::new();
+        options.limits = RateLimits {
+            packets_per_second: 100,
+            column_generations_per_second: 0,
+            max_outstanding_chunk_requests: 2,
+        };
+        let mut game = test_game(options);
+        let peer_id = connect_player(&mut game, "ethan");
+
+        // Without generations left the requests wait
+        for x in 0..2 {
+            let request = ChunkRequest::column(Vector2::new(x, 0));
+            game.handle_packet(peer_id, &Packet::ChunkRequest(request).encode());
+        }
+        game.tick();
+        assert!(game.take_outgoing().is_empty());
+
+        let request = ChunkRequest::column(Vector2::new(2, 0));
+        game.handle_packet(peer_id, &Packet::ChunkRequest(request).encode());
+        assert!(matches!(
+            game.take_outgoing().as_slice(),
+            [Outgoing::Disconnect(id, Some(_))] if *id == peer_id
+        ));
+    }
+
+    #[test]
+    fn test_throttled_chunk_update() {
+        let mut options = GameOptions::new();
+        options.limits.packets_per_second = 2;
+        let mut game = test_game(options);
+        let peer_id = connect_player(&mut game, "ethan");
+
+        // Updates over the limit are still rejected so the client can undo them
+        for sequence in 1..=3 {
+            let update = Packet::ChunkUpdate(ChunkUpdate {
+                block_position: Vector3::new(1, 250, 1),
+                update_type: ChunkUpdateType::PlaceBlockEvent,
+                block_id: 1,
+                sequence,
+            });
+            game.handle_packet(peer_id, &update.encode());
+        }
+        match game.take_outgoing().last() {
+            Some(Outgoing::Send(_, Packet::ChunkUpdateAck(ack))) => {
+                assert_eq!(ack.sequence, 3);
+                assert!(matches!(
+                    &ack.outcome,
+                    ChunkUpdateOutcome::Rejected { reason } if reason.contains("too quickly")
+                ));
+            }
+            other => panic!(
+                "Expected the throttled update to be rejected, got {:?}",
+                other
+            ),
+        }
+    }
+
+    #[test]
+    fn test_view_distance_streaming() {
+        let mut options = GameOptions::new();
+        options.view_distance = 1;
+        // Fast enough to cross ten columns at once
+        options.max_speed = 1000.0;
+        let mut game = test_game(options);
+        let peer_id = game.connect();
+        game.handle_packet(
+            peer_id,
+            &Packet::PlayerConnect(PlayerConnect {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: 0,
+                username: "ethan".to_string(),
+            })
+            .encode(),
+        );
+
+        let streamed_columns = |outgoing: &[Outgoing]| -> Vec<Vector2<i32>> {
+            outgoing
+                .iter()
+                .filter_map(|o| match o {
+                    Outgoing::Send(_, Packet::ChunkContents(contents)) => {
+                        let position = contents.chunks[0].position;
+                        Some(Vector2::new(position.x, position.z))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // The column the player is in comes first
+        let columns = streamed_columns(&game.take_outgoing());
+        assert_eq!(columns.len(), 5);
+        assert_eq!(columns[0], Vector2::new(0, 0));
+        // Columns requested outside the view distance are unloaded along with the streamed ones
+        let request = ChunkRequest::column(Vector2::new(0, 3));
+        game.handle_packet(peer_id, &Packet::ChunkRequest(request).encode());
+        assert_eq!(streamed_columns(&game.take_outgoing()).len(), 1);
+
+        let moved = Packet::PlayerInfoData(PlayerInfoData {
+            username: String::new(),
+            position: Vector3::new(16.0 * 10.0 + 0.5, 80.0, 0.5),
+            rotation: Vector2::new(0.0, 0.0),
+        });
+        game.handle_packet(peer_id, &moved.encode());
+        let outgoing = game.take_outgoing();
+        match &outgoing[0] {
+            Outgoing::Send(_, Packet::UnloadColumns(unload)) => {
+                assert_eq!(unload.column_positions.len(), 6)
+            }
+            other => panic!("Expected the old columns to be unloaded, got {:?}", other),
+        }
+        let columns = streamed_columns(&outgoing);
+        assert_eq!(columns.len(), 5);
+        assert_eq!(columns[0], Vector2::new(10, 0));
+    }
+
+    #[test]
+    fn test_teleport() {
+        let mut options = GameOptions::new();
+        options.view_distance = 1;
+        let mut game = test_game(options);
+        let peer_id = connect_player(&mut game, "ethan");
+        connect_player(&mut game, "alice");
+        game.tick();
+        game.take_outgoing();
+
+        // Only the teleported player is told, and the columns around it are streamed
+        assert!(game.run_console_command("tp 500.5 80 0.5 ethan").is_ok());
+        game.tick();
+        let outgoing = game.take_outgoing();
+        assert!(matches!(
+            outgoing.first(),
+            Some(Outgoing::Send(id, Packet::PlayerInfoData(info)))
+                if *id == peer_id && info.position == Vector3::new(500.5, 80.0, 0.5)
+        ));
+        assert!(!outgoing
+            .iter()
+            .any(|o| matches!(o, Outgoing::Multicast(_, Packet::PlayerInfoData(_)))));
+        assert!(outgoing.iter().any(|o| matches!(
+            o,
+            Outgoing::Send(id, Packet::ChunkContents(contents))
+                if *id == peer_id && contents.chunks[0].position.x == 31
+        )));
+
+        // Moving on from the new position is not mistaken for a jump
+        let info = Packet::PlayerInfoData(PlayerInfoData {
+            username: "ethan".to_string(),
+            position: Vector3::new(501.5, 80.0, 0.5),
+            rotation: Vector2::new(0.0, 0.0),
+        });
+        game.handle_packet(peer_id, &info.encode());
+        assert!(!game
+            .take_outgoing()
+            .iter()
+            .any(|o| matches!(o, Outgoing::Send(_, Packet::PlayerInfoData(_)))));
+    }
+
+    #[test]
+    fn test_console_commands() {
+        let mut game = test_game(GameOptions::new());
+        let peer_id = connect_player(&mut game, "ethan");
+        connect_player(&mut game, "alice");
+
+        let block = Vector3::new(1, 200, 1);
+        assert!(game.run_console_command("setblock 1 200 1 1").is_ok());
+        assert_eq!(game.world.get_block(&block), 1);
+        assert!(game.run_console_command("setblock 1 200 1 -4").is_err());
+        assert!(game.run_console_command("setblock 1 -1 1 0").is_err());
+
+        assert_eq!(
+            game.run_console_command("say hello all"),
+            Ok("[Server] hello all".to_string())
+        );
+        game.take_outgoing();
+
+        // Players cannot kick each other without being operators
+        let command = Packet::Command(Command {
+            command: "kick alice".to_string(),
+        });
+        game.handle_packet(peer_id, &command.encode());
+        game.take_outgoing();
+        assert!(game.sessions.is_online("alice"));
+
+        assert!(game.run_console_command("kick ethan No griefing").is_ok());
+        assert!(matches!(
+            game.take_outgoing().as_slice(),
+            [Outgoing::Disconnect(id, Some(Disconnect { code: DisconnectCode::Kicked, reason }))]
+                if *id == peer_id && reason == "No griefing"
+        ));
+        assert!(game.run_console_command("kick nobody").is_err());
+
+        assert!(!game.stop_requested());
+        assert!(game.run_console_command("stop").is_ok());
+        assert!(game.stop_requested());
+    }
+
+    #[test]
+    fn test_autosave() {
+        let directory = env::temp_dir().join(format!("autosave_test_{}", std::process::id()));
+        let directory = directory.to_str().unwrap().to_string();
+        let mut options = GameOptions::new();
+        options.autosave_interval = 1;
+        let save = SaveFile::with_scripts_root(Some(directory.clone()), REPOSITORY_ROOT);
+        let mut game = Game::new(options, save);
+        let peer_id = connect_player(&mut game, "ethan");
+        let block = Vector3::new(3, 200, 3);
+        game.world.set_block(&block, 1);
+
+        let column_file = format!("{}/columns/0_0.vbdat", directory);
+        for _ in 1..TICKS_PER_SECOND {
+            game.tick();
+        }
+        game.world.get_save_file().flush().unwrap();
+        assert!(!Path::new(&column_file).exists());
+        // Autosaves are written in the background
+        game.tick();
+        game.world.get_save_file().flush().unwrap();
+        assert!(Path::new(&column_file).exists());
+        assert!(Path::new(&format!("{}/players/ethan.vbdat", directory)).exists());
+
+        // Unchanged columns and players are not written again, and no temporary files are left
+        // behind
+        let player_file = format!("{}/players/ethan.vbdat", directory);
+        fs::remove_file(&column_file).unwrap();
+        fs::remove_file(&player_file).unwrap();
+        let standing = Packet::PlayerInfoData(PlayerInfoData {
+            username: "ethan".to_string(),
+            position: Vector3::new(0.0, 80.0, 0.0),
+            rotation: Vector2::new(0.0, 0.0),
+        });
+        game.handle_packet(peer_id, &standing.encode());
+        let request = Packet::PlayerInfoRequest(PlayerInfoRequest {});
+        game.handle_packet(peer_id, &request.encode());
+        for _ in 0..TICKS_PER_SECOND {
+            game.tick();
+        }
+        game.world.get_save_file().flush().unwrap();
+        assert!(!Path::new(&column_file).exists());
+        assert!(!Path::new(&player_file).exists());
+        for subdirectory in ["", "/columns", "/players"] {
+            for entry in fs::read_dir(format!("{}{}", directory, subdirectory)).unwrap() {
+                assert_ne!(entry.unwrap().path().extension(), Some("tmp".as_ref()));
+            }
+        }
+
+        // Changed chunks are loaded back from their column files
+        game.world.set_block(&block, 0);
+        game.world.set_block(&(block + Vector3::new(0, 1, 0)), 1);
+        game.world.flush_save().unwrap();
+        let mut save = SaveFile::new(Some(directory.clone()));
+        save.load().unwrap();
+        let mut loaded = Game::new(GameOptions::new(), save);
+        assert_eq!(loaded.world.get_block(&block), 0);
+        assert_eq!(loaded.world.get_block(&(block + Vector3::new(0, 1, 0))), 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_accounts_converted() {
+        let directory = env::temp_dir().join(format!("accounts_test_{}", std::process::id()));
+        let directory = directory.to_str().unwrap().to_string();
+        let save = SaveFile::with_scripts_root(Some(directory.clone()), REPOSITORY_ROOT);
+        Game::new(GameOptions::new(), save)
+            .world
+            .flush_save()
+            .unwrap();
+
+        // Older saves kept the password hash itself, in a different file
+        let credentials_file = format!("{}/credentials.vbdat", directory);
+        fs::remove_file(&credentials_file).unwrap();
+        let salt = [9; SALT_LENGTH];
+        let legacy: HashMap<String, ([u8; SALT_LENGTH], Hash)> =
+            HashMap::from([("ethan".to_string(), (salt, hash_password(&salt, "hunter2")))]);
+        let legacy_file = format!("{}/accounts.vbdat", directory);
+        fs::write(&legacy_file, bincode::serialize(&legacy).unwrap()).unwrap();
+
+        let mut save = SaveFile::with_scripts_root(Some(directory.clone()), REPOSITORY_ROOT);
+        save.load().unwrap();
+        let mut options = GameOptions::new();
+        options.require_auth = true;
+        let mut game = Game::new(options, save);
+
+        let peer_id = game.connect();
+        game.handle_packet(
+            peer_id,
+            &Packet::PlayerConnect(PlayerConnect {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: 0,
+                username: "ethan".to_string(),
+            })
+            .encode(),
+        );
+        let challenge = match game.take_outgoing().as_slice() {
+            [Outgoing::Send(_, Packet::AuthChallenge(challenge))] => challenge.clone(),
+            outgoing => panic!("Expected a challenge, got {:?}", outgoing),
+        };
+        game.handle_packet(
+            peer_id,
+            &Packet::AuthResponse(AuthResponse {
+                proof: challenge_response(&hash_password(&salt, "hunter2"), &challenge.nonce),
+            })
+            .encode(),
+        );
+        assert!(game.sessions.is_online("ethan"));
+
+        // The converted accounts replace the old file, which could be used to log in
+        game.world.flush_save().unwrap();
+        assert!(!Path::new(&legacy_file).exists());
+        assert!(Path::new(&credentials_file).exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
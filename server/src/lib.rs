@@ -0,0 +1,17 @@
+pub mod auth;
+pub mod commands;
+pub mod config;
+pub mod console;
+pub mod game;
+pub mod items;
+pub mod network;
+pub mod packets;
+pub mod player_data;
+pub mod rate_limit;
+pub mod replay;
+pub mod save_file;
+pub mod save_writer;
+pub mod session;
+pub mod tick;
+pub mod validation;
+pub mod world;
@@ -1,327 +1,222 @@
-mod player_data;
-
-mod save_file;
-use cgmath::Vector2;
-use rlua::Lua;
-use save_file::SaveFile;
-
-use anyhow::Result;
-
-use enet::*;
-use std::net::Ipv4Addr;
+use anyhow::{anyhow, Result};
+use common::cli::flag_value;
+use common::net::resolve_address;
+use common::transport::ip::IpTransport;
+use common::transport::Transport;
+use enet::Enet;
+use server::config::{NetworkConfig, ServerConfig, WorldConfig, DEFAULT_CONFIG_PATH};
+use server::console::Console;
+use server::game::Game;
+use server::network::Server;
+use server::replay::{copy_save, replay, Recorder, ReplayReader};
+use server::save_file::SaveFile;
+use server::tick::{TickTimer, TICKS_PER_SECOND};
+use server::{console_eprintln, console_println};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::env;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{env, fs, str};
-
-mod packets;
-use common::{
-    items::{ItemInfo, ItemManager, ItemType, TextureCoordinates},
-    packets::{ChunkUpdateType, PacketType},
-};
-
-use crate::packets::*;
-
-mod world;
-use world::World;
-
-struct GameOptions {
-    init_only: bool,
+use std::time::{Duration, Instant};
+
+/// Longest time to wait for peers to leave once the server is stopping
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Where the server listens and how many connections it takes
+struct NetworkOptions {
+    /// IP address or hostname to listen on, may include a port
+    bind_address: String,
+    /// Used when `bind_address` does not include a port
+    port: u16,
+    /// Channels allocated for each connection, at least `CHANNEL_COUNT`
+    channel_count: usize,
 }
 
-impl GameOptions {
-    pub fn new() -> Self {
-        GameOptions { init_only: false }
+impl NetworkOptions {
+    fn new(config: &NetworkConfig) -> Self {
+        NetworkOptions {
+            bind_address: config.bind.clone(),
+            port: config.port,
+            channel_count: config.channels,
+        }
     }
+}
 
-    pub fn parse_cli(mut self) -> Self {
-        let args: Vec<String> = env::args().collect();
-
-        self.init_only = args.contains(&"--no_run".to_string());
+/// Where the world is saved
+struct SaveOptions {
+    directory: String,
+    /// Replaces the seed of the save
+    seed: Option<i32>,
+}
 
-        self
+impl SaveOptions {
+    fn new(config: &WorldConfig) -> Self {
+        SaveOptions {
+            directory: config.save_directory.clone(),
+            seed: config.seed,
+        }
     }
 }
 
-struct Game {
-    options: GameOptions,
-
-    server: Host<()>,
-
-    world: World,
+/// Recording sessions to replay files, and replaying them instead of running the server
+struct ReplayOptions {
+    /// Every packet received is recorded to this file, and the save is copied next to it
+    record: Option<String>,
+    /// Replays this file against a copy of the save recorded with it, then exits
+    replay: Option<String>,
 }
 
-impl Game {
-    pub fn new() -> Result<Self> {
-        let options = GameOptions::new().parse_cli();
-        let enet = Enet::new().unwrap();
-        let address = Address::new(Ipv4Addr::UNSPECIFIED, 1234);
-        let server = enet
-            .create_host::<()>(
-                Some(&address),
-                1,
-                ChannelLimit::Maximum,
-                BandwidthLimit::Unlimited,
-                BandwidthLimit::Unlimited,
-            )
-            .unwrap();
-
-        let save_directory = "./save";
-        let mut save = SaveFile::new(Some(save_directory.to_owned()));
-        if save.load().is_err() {
-            eprintln!("Save file could not be loaded with error \"{}\". The save file may not be generated yet!", save.load().unwrap_err());
+impl ReplayOptions {
+    fn new() -> Self {
+        ReplayOptions {
+            record: None,
+            replay: None,
         }
-
-        let mut item_manager = ItemManager::new();
-        load_items(
-            &mut item_manager,
-            save.get_script_path("loadAssetInfo".to_string()),
-        );
-
-        let world = World::new(item_manager, save);
-
-        Ok(Game {
-            options,
-            server,
-            world,
-        })
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        if self.options.init_only {
-            return Ok(());
-        }
-        println!("Running...");
-
-        let term = Arc::new(AtomicBool::new(false));
-        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&term)).unwrap();
-
-        while !term.load(Ordering::Relaxed) {
-            match self.server.service(1000).unwrap() {
-                Some(Event::Connect(_)) => println!("Connected!"),
-                Some(Event::Disconnect(..)) => {
-                    println!("Disconnected!");
-                }
-                Some(Event::Receive {
-                    ref mut sender,
-                    channel_id,
-                    ref packet,
-                    ..
-                }) => {
-                    let data = packet.data();
-                    let packet_type = match PacketType::fromu8(data[0]) {
-                        Ok(p) => p,
-                        Err(_) => {
-                            eprintln!("Unknown packet type with id {}", data[0]);
-                            continue;
-                        }
-                    };
-                    match packet_type {
-                        PacketType::PlayerConnect => {
-                            let username = str::from_utf8(&data[1..(data.len() - 1)]).unwrap();
-                            println!("Player {username} connected!");
-                        }
-                        PacketType::PlayerDisconnect => {
-                            let username = str::from_utf8(&data[1..(data.len() - 1)]).unwrap();
-                            println!("Player {username} has left.");
-                        }
-                        PacketType::PlayerInfoRequest => {
-                            // [0: Type][1-(n-1): username][n: '\0']
-                        let username = str::from_utf8(&data[1..(data.len() - 1)]).unwrap();
-                        let player = self
-                            .world
-                            .get_save_file()
-                            .get_user_data(&username.to_string());
-
-                        let packet_data = assemble_player_info_data(&player);
-                        let packet =
-                            Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
-                        sender.send_packet(packet, channel_id).unwrap();
-                        }
-                        PacketType::PlayerInfoData => {
-                            // [0: Type][1-12: position][13-20: rotation][21-: username]
-                            let username = str::from_utf8(&data[21..(data.len() - 1)]).unwrap();
-                            let position = bincode::deserialize(&data[1..13]).unwrap();
-                            let rotation = bincode::deserialize(&data[13..21]).unwrap();
-
-                            let player = self
-                                .world
-                                .get_save_file()
-                                .get_user_data(&username.to_string());
-                            player.position = position;
-                            player.rotation = rotation;
-                        }
-                        PacketType::ChunkRequest => {
-                            // [0: Type][1-4: column X][5-8: column Z]
-                        let col_x = bincode::deserialize(&data[1..5]).unwrap();
-                        let col_z = bincode::deserialize(&data[5..9]).unwrap();
-                        let col = self.world.get_column(&Vector2::new(col_x, col_z));
-
-                        let packet_data = assemble_chunk_contents_packet(col);
-                        let packet =
-                            Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
-                        sender.send_packet(packet, channel_id).unwrap();
-                        }
-                        PacketType::ChunkUpdate => {
-                            let block_pos = bincode::deserialize(&data[1..13]).unwrap();
-                            let action_type = data[13];
-
-                            let existing_id = self.world.get_block(&block_pos);
-                            if action_type == ChunkUpdateType::PlaceBlockEvent as u8 {
-                                if existing_id > 0 {
-                                    println!(
-                                        "Cannot place block over id {} @ {},{},{}",
-                                        existing_id, block_pos.x, block_pos.y, block_pos.z
-                                    );
-                                } else {
-                                    let block_id: u32 = bincode::deserialize(&data[14..18]).unwrap();
-                                    self.world.set_block(&block_pos, block_id as i32);
-                                }
-                            } else if action_type == ChunkUpdateType::DestroyBlockEvent as u8 {
-                                if existing_id < 1 {
-                                    println!(
-                                        "Cannot destroy empty block id {} @ {},{},{}",
-                                        existing_id, block_pos.x, block_pos.y, block_pos.z
-                                    );
-                                } else {
-                                    self.world.set_block(&block_pos, 0);
-                                }
-                            } else {
-                                println!(
-                                    "Received unknown chunk update type with value {}",
-                                    action_type
-                                );
-                            }
+    fn parse_cli(mut self) -> Self {
+        let args: Vec<String> = env::args().collect();
 
-                            let col_position = World::world_to_column_position(&Vector2::new(
-                                block_pos.x,
-                                block_pos.z,
-                            ));
-                            let packet_data =
-                                assemble_chunk_contents_packet(self.world.get_column(&col_position));
-                            let packet =
-                                Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
-                            sender.send_packet(packet, channel_id).unwrap();
-                        }
-                        PacketType::ChunkContents => eprintln!("Server received \"PacketType::ChunkContents\". Clients should not be sending this..."),
-                    }
-                }
-                _ => (),
-            }
+        if let Some(value) = flag_value(&args, "--record") {
+            self.record = Some(value);
+        }
+        if let Some(value) = flag_value(&args, "--replay") {
+            self.replay = Some(value);
         }
 
-        Ok(())
-    }
-
-    pub fn shutdown(&mut self) -> Result<()> {
-        self.world.save_to_file();
-
-        Ok(())
+        self
     }
 }
 
-fn main() -> Result<()> {
-    let mut game = Game::new()?;
-    game.run()?;
-    game.shutdown()
+/// Where the save is copied to when recording to `replay_path`
+fn recorded_save_directory(replay_path: &str) -> String {
+    format!("{}.save", replay_path)
 }
 
-/// Runs the lua script at the `path` and inserts the new items into the `item_manager`
-pub fn load_items(item_manger: &mut ItemManager, path: String) {
-    let asset_script = fs::read_to_string(path).expect("Unable to load loadAssetInfo script");
-
-    let lua = Lua::new();
-
-    lua.context(|lua_ctx| {
-        let globals = lua_ctx.globals(); // Get globals from lua
-
-        lua_ctx.scope(|scope| {
-
-            let add_asset = // Create a function that takes in all info and compiles it into a ItemInfo struct
-                scope.create_function_mut(|_, (item_name, item_type_str, is_transparent, show_in_inventory, coords): (String, String, bool, bool, Vec<u16>)| {
-
-                    let item_type: ItemType;
-                    match item_type_str.as_str() {
-                        "Air" => item_type = ItemType::Air,
-                        "BlockCube" => item_type = ItemType::BlockCube,
-                        "BlockCross" => item_type = ItemType::BlockCross,
-                        "UserItem" => item_type = ItemType::UserItem,
-                        _ => item_type = ItemType::UserItem,
-                    }
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let config_path =
+        flag_value(&args, "--config").unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+    let config = ServerConfig::load_or_create(&config_path)?.parse_cli();
+    config
+        .validate()
+        .map_err(|e| anyhow!("Invalid command line flags: {}", e))?;
+
+    let mut options = config.game_options();
+    options.init_only = args.iter().any(|arg| arg == "--no_run");
+    let init_only = options.init_only;
+
+    let replay_options = ReplayOptions::new().parse_cli();
+    if let Some(replay_path) = replay_options.replay {
+        return replay_session(&replay_path);
+    }
 
-                    let new_item = ItemInfo {
-                        item_type,
-                        is_transparent,
-                        show_in_inventory,
-                        name: item_name,
-                        top_tex_coords: TextureCoordinates::extract_coordinates(Vector2::new(10, 10),
-                        Vector2::new(coords[0].into(), coords[1].into())),
-                        side_tex_coords: TextureCoordinates::extract_coordinates(Vector2::new(10, 10),
-                        Vector2::new(coords[2].into(), coords[3].into())),
-                        bottom_tex_coords: TextureCoordinates::extract_coordinates(Vector2::new(10, 10),
-                        Vector2::new(coords[4].into(), coords[5].into())),
-                    };
+    let network = NetworkOptions::new(&config.network);
+    let address = resolve_address(&network.bind_address, network.port)?;
 
-                    item_manger.put_new_item(new_item);
+    let max_players = options.max_players;
+    let enet = Enet::new().unwrap();
+    let transport = IpTransport::listen(&enet, address, max_players, network.channel_count)
+        .map_err(|e| anyhow!("Unable to listen on {}: {}", address, e))?;
+    console_println!("Listening on {} for up to {} players", address, max_players);
 
-                    Ok(())
-                }).unwrap();
-            globals.set("add_asset", add_asset).unwrap();
+    let save_options = SaveOptions::new(&config.world);
+    let save_directory = save_options.directory.as_str();
+    let mut save = SaveFile::new(Some(save_directory.to_owned()));
+    if save.load().is_err() {
+        console_eprintln!("Save file could not be loaded with error \"{}\". The save file may not be generated yet!", save.load().unwrap_err());
+    }
+    if let Some(seed) = save_options.seed {
+        save.world_seed = seed;
+    }
 
-            let set_atlas = // Sets which atlas the texture is in
-                lua_ctx.create_function(|_, (atlas_path, width, height): (String, u16, u16)| {
-                    println!("Set Atlas: {}, {}, {}", atlas_path, width, height);
+    let world_seed = save.world_seed;
+    let mut game = Game::new(options, save);
+    if let Some(replay_path) = replay_options.record {
+        // Replays start from the save as it is now, not as it is after the session
+        copy_save(
+            Path::new(save_directory),
+            Path::new(&recorded_save_directory(&replay_path)),
+        )?;
+        let recorder = Recorder::create(Path::new(&replay_path), world_seed, game.options())?;
+        game.start_recording(recorder);
+        console_println!("Recording to {}", replay_path);
+    }
 
-                    Ok(())
-                }).unwrap();
-            globals.set("set_atlas", set_atlas).unwrap();
+    let mut server = Server::new(game, transport);
+    if !init_only {
+        run(&mut server);
+        console_println!("Stopping...");
+        server.stop(SHUTDOWN_TIMEOUT);
+    }
+    server.game.shutdown();
 
-            lua_ctx.load(
-                r#"
-                    item_name = "UNKNOWN"
-                    item_type = "UserItem"
-                    is_transparent = false
-                    show_in_inventory = true
-                    top_coord_x, top_coord_y = 0, 0
-                    side_coord_x, side_coord_y = 0, 0
-                    bottom_coord_x, bottom_coord_y = 0, 0
+    Ok(())
+}
 
-                    function setInfo(name, itemType, isTransparent, showInInventory)
-                        item_name = name or "UNKNOWN"
-                        item_type = itemType or "UserItem"
-                        is_transparent = isTransparent or false
-                        show_in_inventory = showInInventory or true
-                    end
+/// Replays the session recorded to `replay_path` against a copy of the save recorded with it,
+/// with the options it was recorded with rather than those of the config
+fn replay_session(replay_path: &str) -> Result<()> {
+    let reader = ReplayReader::open(Path::new(replay_path))?;
+
+    let save_directory = format!("{}.replay", replay_path);
+    copy_save(
+        Path::new(&recorded_save_directory(replay_path)),
+        Path::new(&save_directory),
+    )
+    .map_err(|e| {
+        anyhow!(
+            "Unable to copy the save recorded with {}: {}",
+            replay_path,
+            e
+        )
+    })?;
+    let mut save = SaveFile::new(Some(save_directory.clone()));
+    if let Err(e) = save.load() {
+        console_eprintln!("Save file could not be loaded with error \"{}\". The save file may not have been generated when recording started!", e);
+    }
+    save.world_seed = reader.world_seed;
+
+    let mut game = Game::new(reader.options.clone(), save);
+    let mut sent = 0;
+    let replayed = replay(&mut game, reader, |outgoing| sent += outgoing.len())?;
+    console_println!(
+        "Replayed {} events, which sent {} packets. The resulting save is in {}",
+        replayed,
+        sent,
+        save_directory
+    );
+    game.shutdown();
+
+    Ok(())
+}
 
-                    function setCoords(topX, topY, sideX, sideY, bottomX, bottomY)
-                        top_coord_x = topX or 0
-                        top_coord_y = topY or 0
-                        side_coord_x = sideX or top_coord_x
-                        side_coord_y = sideY or top_coord_y
-                        bottom_coord_x = bottomX or top_coord_x
-                        bottom_coord_y = bottomY or top_coord_y
-                    end
+/// Runs console lines and ticks the server `TICKS_PER_SECOND` times each second until it is
+/// interrupted, terminated, its terminal hangs up or the `stop` command is run
+fn run<T: Transport>(server: &mut Server<T>) {
+    console_println!("Running at {} ticks per second...", TICKS_PER_SECOND);
 
-                    function pushItem()
-                        add_asset(item_name, item_type, is_transparent, show_in_inventory, {top_coord_x, top_coord_y, side_coord_x, side_coord_y, bottom_coord_x, bottom_coord_y}) -- Change to pull from global variables
-                    end
+    let term = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM, SIGHUP] {
+        signal_hook::flag::register(signal, Arc::clone(&term)).unwrap();
+    }
 
-                    function setAtlas(path, width, height)
-                        width = width or 10
-                        height = height or 10
-                        set_atlas(path, width, height)
-                    end
-                "#
-            )
-            .set_name("Load Asset Functions").unwrap()
-            .exec()
-            .expect("Load asset utility functions failed to load");
+    let console = Console::open();
 
-            lua_ctx
-            .load(&asset_script)
-            .set_name("Load Asset Info").unwrap()
-            .exec()
-            .expect("Lua asset script failed!");
+    let mut timer = TickTimer::new(Instant::now());
+    while !term.load(Ordering::Relaxed) && !server.game.stop_requested() {
+        timer.wait();
+        let started = Instant::now();
+        for line in console.lines() {
+            match server.game.run_console_command(&line) {
+                Ok(output) => console_println!("{}", output),
+                Err(e) => console_eprintln!("{}", e),
+            }
+        }
 
-        });
-    })
+        server.tick();
+        if let Some(warning) = timer.finish(started, Instant::now()) {
+            console_eprintln!("{}", warning);
+        }
+    }
 }
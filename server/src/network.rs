@@ -0,0 +1,233 @@
+use crate::game::{Game, Outgoing};
+use crate::packets::record_sent;
+use crate::session::PeerId;
+use crate::{console_eprintln, console_println};
+use common::packets::{DisconnectCode, EncodedPacket, Packet};
+use common::transport::{ConnectionId, Transport, TransportEvent};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Longest time to wait for a network event while waiting for peers to leave
+const STOP_SERVICE_TIMEOUT: Duration = Duration::from_millis(50);
+/// Most network events handled in one tick, the rest wait for the next so a flood of packets
+/// cannot hold up the world
+const MAX_EVENTS_PER_TICK: usize = 4096;
+
+/// Runs a `Game` over a transport, turning its connections into peers and delivering everything
+/// the game sends
+pub struct Server<T: Transport> {
+    pub game: Game,
+    transport: T,
+    peers: HashMap<ConnectionId, PeerId>,
+    connections: HashMap<PeerId, ConnectionId>,
+    /// Peers that were disconnected but have not left yet, nothing more is sent to them
+    closing: HashSet<PeerId>,
+}
+
+impl<T: Transport> Server<T> {
+    pub fn new(game: Game, transport: T) -> Server<T> {
+        Server {
+            game,
+            transport,
+            peers: HashMap::new(),
+            connections: HashMap::new(),
+            closing: HashSet::new(),
+        }
+    }
+
+    /// Hands every network event that has arrived to the game without waiting for more, runs a
+    /// game tick, then sends everything the game has for its peers
+    pub fn tick(&mut self) {
+        for _ in 0..MAX_EVENTS_PER_TICK {
+            match self.transport.service(Duration::ZERO) {
+                Ok(Some(event)) => self.handle_event(event),
+                Ok(None) => break,
+                Err(e) => {
+                    console_eprintln!("Unable to service the network: {}", e);
+                    break;
+                }
+            }
+        }
+
+        self.game.tick();
+        self.deliver();
+    }
+
+    /// Disconnects every peer with a reason and waits up to `timeout` for them to leave, so
+    /// players are not left waiting for their connection to time out
+    pub fn stop(&mut self, timeout: Duration) {
+        self.game.stop();
+        self.deliver();
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && self.transport.has_connections() {
+            match self.transport.service(STOP_SERVICE_TIMEOUT) {
+                Ok(Some(TransportEvent::Connected(connection))) => self
+                    .transport
+                    .disconnect(connection, DisconnectCode::ServerStopping as u32),
+                Ok(Some(TransportEvent::Disconnected(connection, _))) => {
+                    self.remove_peer(connection)
+                }
+                // Anything else sent by a peer that is leaving is ignored
+                Ok(_) => (),
+                Err(e) => {
+                    console_eprintln!("Unable to service the network: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: TransportEvent) {
+        match event {
+            TransportEvent::Connected(connection) => {
+                console_println!("Connected!");
+                let peer_id = self.game.connect();
+                self.peers.insert(connection, peer_id);
+                self.connections.insert(peer_id, connection);
+            }
+            TransportEvent::Disconnected(connection, _) => {
+                console_println!("Disconnected!");
+                self.remove_peer(connection);
+            }
+            TransportEvent::Received(connection, data) => {
+                if let Some(&peer_id) = self.peers.get(&connection) {
+                    self.game.handle_packet(peer_id, &data);
+                }
+            }
+        }
+    }
+
+    fn remove_peer(&mut self, connection: ConnectionId) {
+        if let Some(peer_id) = self.peers.remove(&connection) {
+            self.connections.remove(&peer_id);
+            self.closing.remove(&peer_id);
+            self.game.disconnect(peer_id);
+        }
+    }
+
+    /// Sends everything the game has for its peers
+    fn deliver(&mut self) {
+        for action in self.game.take_outgoing() {
+            match action {
+                Outgoing::Send(peer_id, packet) => {
+                    let encoded = packet.encode_with(self.game.compresses(peer_id));
+                    self.send_encoded(peer_id, &encoded, &packet);
+                }
+                Outgoing::Multicast(peer_ids, packet) => {
+                    // Encode once for each compression setting rather than once per peer
+                    let mut plain = None;
+                    let mut compressed = None;
+                    for peer_id in peer_ids {
+                        let encoded = match self.game.compresses(peer_id) {
+                            true => compressed.get_or_insert_with(|| packet.encode_with(true)),
+                            false => plain.get_or_insert_with(|| packet.encode_with(false)),
+                        };
+                        self.send_encoded(peer_id, encoded, &packet);
+                    }
+                }
+                Outgoing::Disconnect(peer_id, reason) => {
+                    let mut data = 0;
+                    if let Some(reason) = reason {
+                        data = reason.code as u32;
+                        let packet = Packet::Disconnect(reason);
+                        let encoded = packet.encode_with(self.game.compresses(peer_id));
+                        self.send_encoded(peer_id, &encoded, &packet);
+                    }
+                    // Nothing more is sent to the peer once it is asked to leave
+                    if let Some(&connection) = self.connections.get(&peer_id) {
+                        if self.closing.insert(peer_id) {
+                            self.transport.disconnect(connection, data);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn send_encoded(&mut self, peer_id: PeerId, encoded: &EncodedPacket, packet: &Packet) {
+        if self.closing.contains(&peer_id) {
+            return;
+        }
+        let connection = match self.connections.get(&peer_id) {
+            Some(&connection) => connection,
+            None => return,
+        };
+
+        record_sent(encoded);
+        if let Err(e) = self
+            .transport
+            .send(connection, packet.channel(), &encoded.data)
+        {
+            console_eprintln!("Unable to send packet: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameOptions;
+    use crate::save_file::SaveFile;
+    use common::packets::{Channel, PlayerConnect, PROTOCOL_VERSION};
+    use common::transport::loopback::LoopbackTransport;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Services `server` until `client` has something to report
+    fn next_event(
+        server: &mut Server<LoopbackTransport>,
+        client: &mut LoopbackTransport,
+    ) -> TransportEvent {
+        for _ in 0..10 {
+            server.tick();
+            if let Some(event) = client.service(TIMEOUT).unwrap() {
+                return event;
+            }
+        }
+        panic!("The client heard nothing from the server");
+    }
+
+    #[test]
+    fn test_loopback_server() {
+        let save = SaveFile::with_scripts_root(None, concat!(env!("CARGO_MANIFEST_DIR"), "/.."));
+        let game = Game::new(GameOptions::new(), save);
+        let mut server = Server::new(game, LoopbackTransport::new());
+        let mut client = LoopbackTransport::new();
+
+        let connection = client.connect(&server.transport.address()).unwrap();
+        assert_eq!(
+            next_event(&mut server, &mut client),
+            TransportEvent::Connected(connection)
+        );
+
+        let connect = Packet::PlayerConnect(PlayerConnect {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: 0,
+            username: "ethan".to_string(),
+        });
+        client
+            .send(connection, Channel::Control, &connect.encode())
+            .unwrap();
+        match next_event(&mut server, &mut client) {
+            TransportEvent::Received(_, data) => {
+                assert!(matches!(Packet::decode(&data), Ok(Packet::ServerInfo(_))))
+            }
+            event => panic!("Expected the server info, got {:?}", event),
+        }
+
+        server.stop(TIMEOUT);
+        let mut events = Vec::new();
+        while let Some(event) = client.service(Duration::ZERO).unwrap() {
+            events.push(event);
+        }
+        assert_eq!(
+            events.last(),
+            Some(&TransportEvent::Disconnected(
+                connection,
+                DisconnectCode::ServerStopping as u32
+            ))
+        );
+        assert!(!server.transport.has_connections());
+    }
+}
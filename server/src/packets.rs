@@ -1,50 +1,118 @@
-use crate::{player_data::Player, world::ChunkColumn};
-use common::packets::PacketType;
+use crate::{auth::Account, game::GameOptions, player_data::Player, world::ChunkColumn};
+use cgmath::Vector2;
+use common::auth::NONCE_LENGTH;
+use common::packets::{
+    AuthChallenge, BlockChanged, ChunkContents, ChunkData, ChunkUpdateAck, ChunkUpdateOutcome,
+    EncodedPacket, MessageKind, MultiBlockChange, Packet, PlayerInfoData, ServerInfo,
+    ServerMessage, StatusResponse, UnloadColumns, PROTOCOL_VERSION, SUPPORTED_CAPABILITIES,
+};
+use common::{CHUNK_WIDTH, COLUMN_HEIGHT};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-pub fn assemble_player_info_data(player: &Player) -> Vec<u8> {
-    let mut packet_data = Vec::<u8>::new();
+/// Accepts a connection, enabling the capabilities shared by client and server
+pub fn assemble_server_info(client_capabilities: u32) -> Packet {
+    Packet::ServerInfo(ServerInfo {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: client_capabilities & SUPPORTED_CAPABILITIES,
+        chunk_width: CHUNK_WIDTH,
+        column_height: COLUMN_HEIGHT,
+    })
+}
 
-    packet_data.push(PacketType::PlayerInfoData as u8);
+/// Describes the server to someone who has not logged in
+pub fn assemble_status_response(options: &GameOptions, players: usize, uptime: Duration) -> Packet {
+    Packet::StatusResponse(StatusResponse {
+        protocol_version: PROTOCOL_VERSION,
+        motd: options.motd.clone(),
+        world_name: options.world_name.clone(),
+        players: players as u32,
+        max_players: options.max_players as u32,
+        uptime: uptime.as_secs(),
+    })
+}
 
-    // username
-    for c in player.username.as_bytes() {
-        packet_data.push(*c);
-    }
-    packet_data.push('\0' as u8);
+pub fn assemble_auth_challenge(account: &Account, nonce: &[u8; NONCE_LENGTH]) -> Packet {
+    Packet::AuthChallenge(AuthChallenge {
+        salt: *account.salt(),
+        nonce: *nonce,
+    })
+}
 
-    // position
-    let mut pos = bincode::serialize(&player.position).unwrap();
-    packet_data.append(&mut pos);
+pub fn assemble_player_info_data(player: &Player) -> Packet {
+    Packet::PlayerInfoData(PlayerInfoData {
+        username: player.username.clone(),
+        position: player.position,
+        rotation: player.rotation,
+    })
+}
 
-    // rotation
-    let mut rot = bincode::serialize(&player.rotation).unwrap();
-    packet_data.append(&mut rot);
+/// Describes the chunks of `col` whose heights are in `heights`
+pub fn assemble_chunk_contents_packet(col: &ChunkColumn, heights: RangeInclusive<u8>) -> Packet {
+    let chunks = col.get_chunks()[*heights.start() as usize..=*heights.end() as usize]
+        .iter()
+        .map(ChunkData::from_chunk)
+        .collect();
 
-    packet_data
+    Packet::ChunkContents(ChunkContents { chunks })
 }
 
-pub fn assemble_chunk_contents_packet(col: &mut ChunkColumn) -> Vec<u8> {
-    let mut packet_data = Vec::<u8>::new();
+/// Sends a single change on its own and batches any more into one packet
+pub fn assemble_block_changes(mut changes: Vec<BlockChanged>) -> Packet {
+    if changes.len() == 1 {
+        Packet::BlockChanged(changes.remove(0))
+    } else {
+        Packet::MultiBlockChange(MultiBlockChange { changes })
+    }
+}
 
-    packet_data.push(PacketType::ChunkContents as u8);
+pub fn assemble_chunk_update_ack(sequence: u32, outcome: ChunkUpdateOutcome) -> Packet {
+    Packet::ChunkUpdateAck(ChunkUpdateAck { sequence, outcome })
+}
 
-    let chunks = col.get_chunks();
-    for chunk in chunks {
-        let mut pos = bincode::serialize(&chunk.position).unwrap();
-        packet_data.append(&mut pos);
+pub fn assemble_unload_columns(column_positions: Vec<Vector2<i32>>) -> Packet {
+    Packet::UnloadColumns(UnloadColumns { column_positions })
+}
 
-        let compressed_data = chunk.compress();
+pub fn assemble_chat_message(sender: &str, message: &str) -> Packet {
+    Packet::ServerMessage(ServerMessage {
+        kind: MessageKind::Chat,
+        sender: Some(sender.to_string()),
+        message: message.to_string(),
+    })
+}
 
-        for set in compressed_data {
-            let mut id = bincode::serialize(&set.id).unwrap();
-            packet_data.append(&mut id);
-            let mut count = bincode::serialize(&set.count).unwrap();
-            packet_data.append(&mut count);
-        }
+pub fn assemble_system_message(message: &str) -> Packet {
+    Packet::ServerMessage(ServerMessage {
+        kind: MessageKind::System,
+        sender: None,
+        message: message.to_string(),
+    })
+}
 
-        let mut end_indicator = bincode::serialize(&(-1)).unwrap();
-        packet_data.append(&mut end_indicator);
-    }
+/// Payload bytes of every packet sent, before compression
+static UNCOMPRESSED_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Payload bytes actually sent, after compression
+static COMPRESSED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts `encoded` towards the compression report, once for each peer it is sent to
+pub fn record_sent(encoded: &EncodedPacket) {
+    UNCOMPRESSED_BYTES.fetch_add(encoded.uncompressed_size as u64, Ordering::Relaxed);
+    COMPRESSED_BYTES.fetch_add(encoded.data.len() as u64 - 1, Ordering::Relaxed);
+}
+
+/// Describes how much compression has shrunk the payloads sent so far
+pub fn compression_report() -> String {
+    let uncompressed = UNCOMPRESSED_BYTES.load(Ordering::Relaxed);
+    let compressed = COMPRESSED_BYTES.load(Ordering::Relaxed);
+    let ratio = match compressed {
+        0 => 1.0,
+        _ => uncompressed as f64 / compressed as f64,
+    };
 
-    packet_data
+    format!(
+        "Sent {} bytes of packet payloads as {} bytes, a compression ratio of {:.2}",
+        uncompressed, compressed, ratio
+    )
 }
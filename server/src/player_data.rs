@@ -1,9 +1,51 @@
+use anyhow::Result;
 use cgmath::{Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ItemStack {
+    pub id: i32,
+    pub count: u32,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Player {
     pub username: String,
     pub position: Vector3<f32>,
     pub rotation: Vector2<f32>,
+    pub inventory: Vec<ItemStack>,
+}
+
+/// Player data as saved before inventories were added
+#[derive(Deserialize)]
+struct LegacyPlayer {
+    username: String,
+    position: Vector3<f32>,
+    rotation: Vector2<f32>,
+}
+
+impl Player {
+    /// Reads player data written by this or an older version of the server
+    pub fn deserialize(buffer: &[u8]) -> Result<Player> {
+        match bincode::deserialize::<Player>(buffer) {
+            Ok(player) => Ok(player),
+            Err(e) => {
+                let legacy: LegacyPlayer = bincode::deserialize(buffer).map_err(|_| e)?;
+                Ok(Player {
+                    username: legacy.username,
+                    position: legacy.position,
+                    rotation: legacy.rotation,
+                    inventory: Vec::new(),
+                })
+            }
+        }
+    }
+
+    /// Adds `count` items of `id` to the inventory, stacking with existing items
+    pub fn give_item(&mut self, id: i32, count: u32) {
+        match self.inventory.iter_mut().find(|stack| stack.id == id) {
+            Some(stack) => stack.count = stack.count.saturating_add(count),
+            None => self.inventory.push(ItemStack { id, count }),
+        }
+    }
 }
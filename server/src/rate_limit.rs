@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// Budgets every peer gets, see `RulesConfig` for how to change them
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimits {
+    pub packets_per_second: u32,
+    /// Columns a peer may cause to be generated each second, requests for columns that
+    /// already exist are not limited
+    pub column_generations_per_second: u32,
+    /// Chunk requests a peer may have waiting before it is disconnected
+    pub max_outstanding_chunk_requests: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            packets_per_second: 200,
+            column_generations_per_second: 16,
+            max_outstanding_chunk_requests: 128,
+        }
+    }
+}
+
+/// Packets dropped because their peer was over budget
+static PACKETS_THROTTLED: AtomicU64 = AtomicU64::new(0);
+/// Peers disconnected for going over a budget
+static PEERS_KICKED: AtomicU64 = AtomicU64::new(0);
+/// Columns generated to answer chunk requests
+static COLUMNS_GENERATED: AtomicU64 = AtomicU64::new(0);
+/// Times a waiting chunk request was held back because its peer was out of column generations
+static GENERATIONS_DEFERRED: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_kick() {
+    PEERS_KICKED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_generation() {
+    COLUMNS_GENERATED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_deferred_generation() {
+    GENERATIONS_DEFERRED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Describes how often peers have run into their budgets so far
+pub fn rate_limit_report() -> String {
+    format!(
+        "Throttled {} packets, kicked {} peers for flooding, generated {} columns for chunk requests and held back requests {} times",
+        PACKETS_THROTTLED.load(Ordering::Relaxed),
+        PEERS_KICKED.load(Ordering::Relaxed),
+        COLUMNS_GENERATED.load(Ordering::Relaxed),
+        GENERATIONS_DEFERRED.load(Ordering::Relaxed),
+    )
+}
+
+/// Allows `per_second` actions each second on average, and bursts of up to a second's worth
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_second: u32, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity: per_second as f64,
+            tokens: per_second as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Uses up one token if there are any left
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.capacity).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// What to do with a packet from a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Drop the packet, the peer is over budget
+    Throttle,
+    /// The peer kept sending after being throttled for a second's worth of packets
+    Kick,
+}
+
+/// Tracks how much of its budgets one peer has used
+pub struct PeerLimiter {
+    packets_per_second: u32,
+    packets: TokenBucket,
+    generations: TokenBucket,
+    /// Packets dropped since the peer last stayed within its budget
+    excess_packets: u32,
+}
+
+impl PeerLimiter {
+    pub fn new(limits: &RateLimits, now: Instant) -> PeerLimiter {
+        PeerLimiter {
+            packets_per_second: limits.packets_per_second,
+            packets: TokenBucket::new(limits.packets_per_second, now),
+            generations: TokenBucket::new(limits.column_generations_per_second, now),
+            excess_packets: 0,
+        }
+    }
+
+    pub fn check_packet(&mut self, now: Instant) -> Verdict {
+        if self.packets.try_take(now) {
+            self.excess_packets = 0;
+            return Verdict::Allow;
+        }
+
+        PACKETS_THROTTLED.fetch_add(1, Ordering::Relaxed);
+        self.excess_packets += 1;
+        match self.excess_packets > self.packets_per_second {
+            true => Verdict::Kick,
+            false => Verdict::Throttle,
+        }
+    }
+
+    /// Uses up one column generation if the peer has any left
+    pub fn try_generate(&mut self, now: Instant) -> bool {
+        self.generations.try_take(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_peer_limiter() {
+        let limits = RateLimits {
+            packets_per_second: 10,
+            column_generations_per_second: 2,
+            max_outstanding_chunk_requests: 4,
+        };
+        let start = Instant::now();
+        let mut limiter = PeerLimiter::new(&limits, start);
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check_packet(start), Verdict::Allow);
+        }
+        for _ in 0..10 {
+            assert_eq!(limiter.check_packet(start), Verdict::Throttle);
+        }
+        assert_eq!(limiter.check_packet(start), Verdict::Kick);
+
+        // Half a second refills half of the budget
+        let later = start + Duration::from_millis(500);
+        for _ in 0..5 {
+            assert_eq!(limiter.check_packet(later), Verdict::Allow);
+        }
+        assert_eq!(limiter.check_packet(later), Verdict::Throttle);
+
+        assert!(limiter.try_generate(start));
+        assert!(limiter.try_generate(start));
+        assert!(!limiter.try_generate(start));
+        assert!(limiter.try_generate(later));
+    }
+}
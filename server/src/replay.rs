@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use common::packets::{Command, Packet, PROTOCOL_VERSION};
+use serde::{Deserialize, Serialize};
+
+use crate::commands::redact_credentials;
+use crate::game::{Game, GameOptions, Outgoing};
+use crate::session::PeerId;
+use crate::{console_eprintln, console_println};
+
+/// Start of every replay file
+const MAGIC: [u8; 8] = *b"VBREPLAY";
+/// Changed whenever the layout of replay files changes
+const FORMAT_VERSION: u32 = 3;
+
+/// Something the game was told to do, in the order it happened
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RecordedEvent {
+    Connect(PeerId),
+    Disconnect(PeerId),
+    /// A packet as it arrived, before it was decoded
+    Packet(PeerId, Vec<u8>),
+    Tick,
+    Console(String),
+}
+
+impl RecordedEvent {
+    /// Records a packet, with the password taken out of any command that sets one
+    pub fn packet(peer_id: PeerId, data: &[u8]) -> RecordedEvent {
+        if let Ok(Packet::Command(command)) = Packet::decode(data) {
+            let redacted = Packet::Command(Command {
+                command: redact_credentials(&command.command),
+            });
+            return RecordedEvent::Packet(peer_id, redacted.encode());
+        }
+        RecordedEvent::Packet(peer_id, data.to_vec())
+    }
+
+    /// Records a console line, with the password taken out of any command that sets one
+    pub fn console(line: &str) -> RecordedEvent {
+        RecordedEvent::Console(redact_credentials(line))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// Time since the recording started in nanoseconds, so replayed rate limits see exactly the
+    /// same gaps between events
+    pub elapsed_ns: u64,
+    pub event: RecordedEvent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Header {
+    magic: [u8; 8],
+    format_version: u32,
+    protocol_version: u32,
+    /// Columns generated during the replay only match the recording with the same seed
+    world_seed: i32,
+    /// Replays only behave the same with the view distance, limits and rules they were recorded
+    /// with
+    options: GameOptions,
+}
+
+/// Writes everything the game is told to do to a replay file
+pub struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, world_seed: i32, options: &GameOptions) -> Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = Header {
+            magic: MAGIC,
+            format_version: FORMAT_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            world_seed,
+            options: options.clone(),
+        };
+        bincode::serialize_into(&mut writer, &header)?;
+        Ok(Recorder {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, now: Instant, event: RecordedEvent) {
+        // Ticks happen many times a second, so little is lost if the server crashes
+        let flush = event == RecordedEvent::Tick;
+        let entry = Entry {
+            elapsed_ns: now.saturating_duration_since(self.started).as_nanos() as u64,
+            event,
+        };
+        let result = bincode::serialize_into(&mut self.writer, &entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| match flush {
+                true => Ok(self.writer.flush()?),
+                false => Ok(()),
+            });
+        if let Err(e) = result {
+            console_eprintln!("Unable to record to the replay file: {}", e);
+        }
+    }
+}
+
+/// Reads the entries of a replay file in order
+pub struct ReplayReader {
+    reader: BufReader<File>,
+    pub world_seed: i32,
+    /// The options the game was recorded with, which it has to be replayed with
+    pub options: GameOptions,
+}
+
+impl ReplayReader {
+    pub fn open(path: &Path) -> Result<ReplayReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header: Header = bincode::deserialize_from(&mut reader)?;
+        if header.magic != MAGIC {
+            bail!("{} is not a replay file", path.display());
+        }
+        if header.format_version != FORMAT_VERSION {
+            bail!(
+                "{} uses replay format {}, but this server reads format {}",
+                path.display(),
+                header.format_version,
+                FORMAT_VERSION
+            );
+        }
+        if header.protocol_version != PROTOCOL_VERSION {
+            bail!(
+                "{} was recorded with protocol version {}, but this server uses {}",
+                path.display(),
+                header.protocol_version,
+                PROTOCOL_VERSION
+            );
+        }
+        Ok(ReplayReader {
+            reader,
+            world_seed: header.world_seed,
+            options: header.options,
+        })
+    }
+}
+
+impl Iterator for ReplayReader {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        match bincode::deserialize_from(&mut self.reader) {
+            Ok(entry) => Some(Ok(entry)),
+            // The end of the file, or an entry cut short by a crash
+            Err(e) => match *e {
+                bincode::ErrorKind::Io(ref io) if io.kind() == ErrorKind::UnexpectedEof => None,
+                _ => Some(Err(e.into())),
+            },
+        }
+    }
+}
+
+/// Feeds recorded entries to `game` at the times they were recorded, handing what it sends to
+/// `on_outgoing`, and returns how many entries were replayed. Logins with passwords cannot be
+/// replayed, as their challenges are random
+pub fn replay(
+    game: &mut Game,
+    entries: impl Iterator<Item = Result<Entry>>,
+    mut on_outgoing: impl FnMut(Vec<Outgoing>),
+) -> Result<usize> {
+    let started = Instant::now();
+    // Recorded peers are given new ids as they connect again
+    let mut peers: HashMap<PeerId, PeerId> = HashMap::new();
+    let mut replayed = 0;
+
+    for entry in entries {
+        let entry = entry?;
+        game.set_time(started + Duration::from_nanos(entry.elapsed_ns));
+        match entry.event {
+            RecordedEvent::Connect(recorded) => {
+                peers.insert(recorded, game.connect());
+            }
+            RecordedEvent::Disconnect(recorded) => {
+                if let Some(peer_id) = peers.remove(&recorded) {
+                    game.disconnect(peer_id);
+                }
+            }
+            RecordedEvent::Packet(recorded, data) => {
+                if let Some(&peer_id) = peers.get(&recorded) {
+                    game.handle_packet(peer_id, &data);
+                }
+            }
+            RecordedEvent::Tick => game.tick(),
+            RecordedEvent::Console(line) => match game.run_console_command(&line) {
+                Ok(output) => console_println!("{}", output),
+                Err(e) => console_eprintln!("{}", e),
+            },
+        }
+        on_outgoing(game.take_outgoing());
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+/// Copies the save directory `from` to `to`, replacing whatever was at `to`
+pub fn copy_save(from: &Path, to: &Path) -> Result<()> {
+    if to.exists() {
+        fs::remove_dir_all(to)?;
+    }
+    copy_directory(from, to)
+}
+
+fn copy_directory(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_directory(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameOptions;
+    use crate::save_file::SaveFile;
+    use cgmath::Vector2;
+    use common::packets::{ChunkRequest, Packet, PlayerConnect};
+    use std::env;
+
+    /// Runs a short session, returning what the game sent
+    fn run_session(game: &mut Game) -> Vec<Outgoing> {
+        let mut outgoing = Vec::new();
+        let peer_id = game.connect();
+        let connect = Packet::PlayerConnect(PlayerConnect {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: 0,
+            username: "ethan".to_string(),
+        });
+        game.handle_packet(peer_id, &connect.encode());
+        outgoing.extend(game.take_outgoing());
+        let request = Packet::ChunkRequest(ChunkRequest {
+            column_position: Vector2::new(40, -3),
+            min_height: 0,
+            max_height: 3,
+        });
+        game.handle_packet(peer_id, &request.encode());
+        outgoing.extend(game.take_outgoing());
+        game.tick();
+        outgoing.extend(game.take_outgoing());
+        game.disconnect(peer_id);
+        outgoing.extend(game.take_outgoing());
+        outgoing
+    }
+
+    fn new_game(world_seed: i32, options: GameOptions) -> Game {
+        let mut save =
+            SaveFile::with_scripts_root(None, concat!(env!("CARGO_MANIFEST_DIR"), "/.."));
+        save.world_seed = world_seed;
+        Game::new(options, save)
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = env::temp_dir().join(format!("replay_test_{}.vbreplay", std::process::id()));
+
+        let mut options = GameOptions::new();
+        options.view_distance = 2;
+        options.limits.packets_per_second = 7;
+        let mut game = new_game(1234, options.clone());
+        game.start_recording(Recorder::create(&path, 1234, &options).unwrap());
+        let recorded = run_session(&mut game);
+        drop(game);
+
+        let reader = ReplayReader::open(&path).unwrap();
+        assert_eq!(reader.world_seed, 1234);
+        assert_eq!(reader.options, options);
+        let entries: Vec<Entry> = reader.map(|entry| entry.unwrap()).collect();
+        assert!(matches!(entries[0].event, RecordedEvent::Connect(_)));
+        assert!(matches!(
+            entries.last().unwrap().event,
+            RecordedEvent::Disconnect(_)
+        ));
+
+        // Replaying sends the same chunks as the recorded session did
+        let mut replayed = Vec::new();
+        let count = replay(
+            &mut new_game(1234, options),
+            entries.into_iter().map(Ok),
+            |outgoing| replayed.extend(outgoing),
+        );
+        assert_eq!(count.unwrap(), 5);
+        assert!(recorded
+            .iter()
+            .any(|action| matches!(action, Outgoing::Send(_, Packet::ChunkContents(_)))));
+        assert_eq!(recorded, replayed);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_credentials_not_recorded() {
+        let path = env::temp_dir().join(format!(
+            "replay_credentials_test_{}.vbreplay",
+            std::process::id()
+        ));
+
+        let mut game = new_game(1234, GameOptions::new());
+        game.start_recording(Recorder::create(&path, 1234, &GameOptions::new()).unwrap());
+        let peer_id = game.connect();
+        let connect = Packet::PlayerConnect(PlayerConnect {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: 0,
+            username: "ethan".to_string(),
+        });
+        game.handle_packet(peer_id, &connect.encode());
+        let command = Packet::Command(Command {
+            command: "/password swordfish".to_string(),
+        });
+        game.handle_packet(peer_id, &command.encode());
+        assert!(game
+            .run_console_command("account set alice hunter2")
+            .is_ok());
+        drop(game);
+
+        let contents = fs::read(&path).unwrap();
+        let contains = |text: &str| {
+            contents
+                .windows(text.len())
+                .any(|window| window == text.as_bytes())
+        };
+        assert!(contains("password <redacted>"));
+        assert!(contains("account set alice <redacted>"));
+        assert!(!contains("swordfish"));
+        assert!(!contains("hunter2"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,22 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use cgmath::{Vector2, Vector3};
 
+use crate::auth::{Account, LegacyAccount};
 use crate::player_data::Player;
+use crate::save_writer::SaveWriter;
+use crate::{console_eprintln, console_println};
 
 use crate::world::BlockToPlace;
-use common::{Chunk, CompressedSet};
+use common::{Chunk, CompressedSet, COLUMN_HEIGHT};
 
 const DEFAULT_SCRIPT_SUBDIRECTORY: &str = "/default_scripts";
 const SAVE_FILE_NAME: &str = "worldData";
 const SAVE_FILE_EXTENSION: &str = "vbdat";
 const PLAYER_SAVE_SUBDIRECTORY: &str = "/players";
+/// Each column is saved to its own file, so only the changed ones need writing
+const COLUMN_SAVE_SUBDIRECTORY: &str = "/columns";
 const SCRIPT_SAVE_SUBDIRECTORY: &str = "/scripts";
+const OPERATORS_FILE_NAME: &str = "operators.txt";
+const ACCOUNTS_FILE_NAME: &str = "credentials";
+/// Held accounts before they were saved as stored keys, the password hashes in it can log in
+const LEGACY_ACCOUNTS_FILE_NAME: &str = "accounts";
 
 pub struct ChunkInfo {
     pub position: Vector3<i32>,
@@ -26,14 +35,34 @@ pub struct ChunkInfo {
 pub struct SaveFile {
     // save_directory does not contain trailing slashes, if None do not save
     pub save_directory: Option<String>,
+    /// Directory holding the default scripts, which new saves start with a copy of
+    scripts_root: String,
     pub world_seed: i32,
-    chunk_data: Vec<ChunkInfo>,
+    pub world_time: u32,
+    chunk_data: HashMap<Vector3<i32>, ChunkInfo>,
     block_to_place: Vec<BlockToPlace>,
     players: HashMap<String, Player>,
+    operators: Vec<String>,
+    accounts: HashMap<String, Account>,
+    /// The legacy accounts file the accounts were converted from, deleted once they are saved
+    legacy_accounts: Option<PathBuf>,
+    /// Whether the accounts converted from `legacy_accounts` have been queued to be written
+    legacy_accounts_replaced: bool,
+    /// Columns and players changed since the save was last written
+    dirty_columns: HashSet<Vector2<i32>>,
+    dirty_players: HashSet<String>,
+    /// Started by the first save
+    writer: Option<SaveWriter>,
 }
 
 impl SaveFile {
     pub fn new(directory: Option<String>) -> SaveFile {
+        SaveFile::with_scripts_root(directory, ".")
+    }
+
+    /// Creates a save that reads the default scripts from under `scripts_root` rather than the
+    /// working directory
+    pub fn with_scripts_root(directory: Option<String>, scripts_root: &str) -> SaveFile {
         match directory.clone() {
             Some(dir) => {
                 assert!(!dir.is_empty(), "Empty save directory entered!");
@@ -45,7 +74,7 @@ impl SaveFile {
                     !dir.ends_with('/'),
                     "Save directory may not end with / character"
                 );
-                match SaveFile::generate_save_structure(dir) {
+                match SaveFile::generate_save_structure(dir, scripts_root) {
                     Ok(_) => (),
                     Err(e) => panic!("Unable to generate save directory structure: {}", e),
                 }
@@ -55,16 +84,26 @@ impl SaveFile {
 
         SaveFile {
             save_directory: directory,
+            scripts_root: scripts_root.to_string(),
             world_seed: rand::random(),
-            chunk_data: Vec::<ChunkInfo>::new(),
+            world_time: 0,
+            chunk_data: HashMap::new(),
             block_to_place: Vec::<BlockToPlace>::new(),
             players: HashMap::new(),
+            operators: Vec::new(),
+            accounts: HashMap::new(),
+            legacy_accounts: None,
+            legacy_accounts_replaced: false,
+            dirty_columns: HashSet::new(),
+            dirty_players: HashSet::new(),
+            writer: None,
         }
     }
 
-    fn generate_save_structure(directory: String) -> io::Result<()> {
+    fn generate_save_structure(directory: String, scripts_root: &str) -> io::Result<()> {
         fs::create_dir_all(format!("{}{}", directory, SCRIPT_SAVE_SUBDIRECTORY))?;
         fs::create_dir_all(format!("{}{}", directory, PLAYER_SAVE_SUBDIRECTORY))?;
+        fs::create_dir_all(format!("{}{}", directory, COLUMN_SAVE_SUBDIRECTORY))?;
 
         let script_files = ["loadAssetInfo.lua", "generateChunkColumn.lua"];
 
@@ -72,7 +111,10 @@ impl SaveFile {
             let to_path_str = format!("{}{}/{}", directory, SCRIPT_SAVE_SUBDIRECTORY, script_file);
             if !Path::new(&to_path_str).exists() {
                 fs::copy(
-                    format!(".{}/{}", DEFAULT_SCRIPT_SUBDIRECTORY, script_file),
+                    format!(
+                        "{}{}/{}",
+                        scripts_root, DEFAULT_SCRIPT_SUBDIRECTORY, script_file
+                    ),
                     to_path_str,
                 )?;
             }
@@ -87,113 +129,194 @@ impl SaveFile {
                 "{}{}/{}.lua",
                 directory, SCRIPT_SAVE_SUBDIRECTORY, script_name
             ),
-            None => format!(".{}/{}.lua", DEFAULT_SCRIPT_SUBDIRECTORY, script_name),
+            None => format!(
+                "{}{}/{}.lua",
+                self.scripts_root, DEFAULT_SCRIPT_SUBDIRECTORY, script_name
+            ),
         }
     }
 
     pub fn get_chunk(&self, position: Vector3<i32>) -> Option<&ChunkInfo> {
-        for chunk in self.chunk_data.as_slice() {
-            if chunk.position == position {
-                return Some(chunk);
-            }
-        }
+        self.chunk_data.get(&position)
+    }
 
-        None
+    /// Gets the data of `username`, creating it for new players
+    pub fn get_user_data(&mut self, username: &String) -> &Player {
+        self.player_entry(username)
     }
 
-    pub fn get_user_data(&mut self, username: &String) -> &mut Player {
+    /// Gets the data of `username` to change it, so it is written with the next save
+    pub fn get_user_data_mut(&mut self, username: &String) -> &mut Player {
+        self.dirty_players.insert(username.to_string());
+        self.player_entry(username)
+    }
+
+    fn player_entry(&mut self, username: &String) -> &mut Player {
         if self.players.contains_key(username) {
             return self.players.get_mut(username).unwrap();
         }
 
+        // New players are saved even if nothing about them changes
+        self.dirty_players.insert(username.to_string());
         let player = Player {
             username: username.to_string(),
             position: Vector3::new(0.0, 80.0, 0.0),
             rotation: Vector2::new(0.0, 0.0),
+            inventory: Vec::new(),
         };
         self.players.insert(username.to_string(), player);
 
         self.players.get_mut(username).unwrap()
     }
 
+    pub fn is_operator(&self, username: &str) -> bool {
+        self.operators.iter().any(|op| op == username)
+    }
+
+    /// Grants or revokes operator permissions for `username`
+    pub fn set_operator(&mut self, username: &str, is_operator: bool) {
+        self.operators.retain(|op| op != username);
+        if is_operator {
+            self.operators.push(username.to_string());
+        }
+    }
+
+    pub fn get_account(&self, username: &str) -> Option<&Account> {
+        self.accounts.get(username)
+    }
+
+    /// Creates or replaces the account of `username`
+    pub fn set_account(&mut self, username: &str, account: Account) {
+        self.accounts.insert(username.to_string(), account);
+    }
+
+    /// Returns whether `username` had an account
+    pub fn remove_account(&mut self, username: &str) -> bool {
+        self.accounts.remove(username).is_some()
+    }
+
     pub fn save_chunk_data(&mut self, chunk: &Chunk) {
         let data = ChunkInfo {
             position: chunk.position,
             data: chunk.compress(),
         };
 
-        let mut remove_index = usize::MAX;
-        for i in 0..self.chunk_data.len() {
-            if self.chunk_data[i].position == chunk.position {
-                remove_index = i;
-            }
-        }
-
-        if remove_index != usize::MAX {
-            self.chunk_data.remove(remove_index);
-        }
-
-        self.chunk_data.push(data)
+        self.dirty_columns
+            .insert(Vector2::new(chunk.position.x, chunk.position.z));
+        self.chunk_data.insert(chunk.position, data);
     }
 
-    pub fn write_save(&self) -> Result<()> {
+    /// Queues the changed columns and players to be written, along with the rest of the world
+    /// data, without waiting for them to reach the disk. Every file is replaced in one step, so a
+    /// crash while saving leaves the previous version of it whole
+    pub fn write_save(&mut self) -> Result<()> {
         if self.save_directory.is_none() {
-            eprintln!("Save directory not provided, save will not be written");
+            console_eprintln!("Save directory not provided, save will not be written");
             return Err(anyhow::Error::new(std::io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "No save directory given!",
             )));
         }
         let directory_str = self.save_directory.clone().unwrap();
+        let mut files: Vec<(PathBuf, Vec<u8>)> = Vec::new();
 
         // Player data
-        for (username, player) in &self.players {
-            let mut file = match File::create(format!(
-                "{}{}/{}.{}",
-                directory_str, PLAYER_SAVE_SUBDIRECTORY, username, SAVE_FILE_EXTENSION
-            )) {
-                Ok(file) => file,
-                Err(e) => {
-                    eprintln!(
-                        "Unable to write save file for player \"{}\" with error \"{}\"",
-                        username, e
-                    );
-                    continue;
+        for username in &self.dirty_players {
+            if let Some(player) = self.players.get(username) {
+                let path = format!(
+                    "{}{}/{}.{}",
+                    directory_str, PLAYER_SAVE_SUBDIRECTORY, username, SAVE_FILE_EXTENSION
+                );
+                files.push((path.into(), bincode::serialize(&player)?));
+            }
+        }
+
+        // Operators
+        let mut operators = self.operators.join("\n");
+        operators.push('\n');
+        files.push((
+            format!("{}/{}", directory_str, OPERATORS_FILE_NAME).into(),
+            operators.into_bytes(),
+        ));
+
+        // Accounts
+        files.push((
+            format!(
+                "{}/{}.{}",
+                directory_str, ACCOUNTS_FILE_NAME, SAVE_FILE_EXTENSION
+            )
+            .into(),
+            bincode::serialize(&self.accounts)?,
+        ));
+
+        // Compressed chunk data, written before the world data as saves from before columns had
+        // their own files still hold chunks there, which the column files replace
+        for column in &self.dirty_columns {
+            let mut data = Vec::new();
+            for height in 0..COLUMN_HEIGHT as i32 {
+                if let Some(chunk) = self
+                    .chunk_data
+                    .get(&Vector3::new(column.x, height, column.y))
+                {
+                    write_chunk(&mut data, chunk)?;
                 }
-            };
-            let binary_player = bincode::serialize(&player)?;
-            file.write(&binary_player)?;
+            }
+            let path = format!(
+                "{}{}/{}_{}.{}",
+                directory_str, COLUMN_SAVE_SUBDIRECTORY, column.x, column.y, SAVE_FILE_EXTENSION
+            );
+            files.push((path.into(), data));
         }
 
         // World data
-        let mut file = match File::create(format!(
-            "{}/{}.{}",
-            directory_str, SAVE_FILE_NAME, SAVE_FILE_EXTENSION
-        )) {
-            Ok(file) => file,
-            Err(e) => return Err(anyhow::Error::new(e)),
-        };
+        let mut data = Vec::new();
 
         // World seed
-        file.write(&bincode::serialize(&self.world_seed)?)?;
-
-        // Compressed chunk data
-        for chunk in &self.chunk_data {
-            file.write(&[b'C'])?;
-            // Chunk Position
-            file.write(&bincode::serialize(&chunk.position)?)?;
-            file.write(&bincode::serialize(&(chunk.data.len() as u32))?)?;
-            for set in &chunk.data {
-                file.write(&bincode::serialize(&set)?)?;
-            }
-        }
+        data.write_all(&bincode::serialize(&self.world_seed)?)?;
+
+        // World time
+        data.write_all(b"T")?;
+        data.write_all(&bincode::serialize(&self.world_time)?)?;
 
         // Blocks to place
         for block in &self.block_to_place {
-            file.write(&[b'N'])?;
-            file.write(&bincode::serialize(&block)?)?;
+            data.write_all(&[b'N'])?;
+            data.write_all(&bincode::serialize(&block)?)?;
         }
 
+        let world_data = (
+            format!(
+                "{}/{}.{}",
+                directory_str, SAVE_FILE_NAME, SAVE_FILE_EXTENSION
+            )
+            .into(),
+            data,
+        );
+
+        // Files that cannot be written are retried by the writer, so nothing needs to stay dirty
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(SaveWriter::new()?),
+        };
+        writer.write(files)?;
+        writer.write_after(vec![world_data])?;
+        self.legacy_accounts_replaced = self.legacy_accounts.is_some();
+        self.dirty_players.clear();
+        self.dirty_columns.clear();
+
+        Ok(())
+    }
+
+    /// Waits for every save queued so far to be written
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(writer) = &self.writer {
+            writer.flush()?;
+        }
+        if self.legacy_accounts_replaced {
+            if let Some(path) = self.legacy_accounts.take() {
+                fs::remove_file(&path)?;
+            }
+        }
         Ok(())
     }
 
@@ -206,12 +329,16 @@ impl SaveFile {
             Ok(contents) => {
                 for entry in contents {
                     let path = entry?.path();
+                    if !is_save_file(&path) {
+                        continue;
+                    }
                     let mut in_file = match File::open(path.clone()) {
                         Ok(file) => file,
                         Err(e) => {
-                            eprintln!(
+                            console_eprintln!(
                                 "Unable to write save file \"{:?}\" with error \"{}\"",
-                                path, e
+                                path,
+                                e
                             );
                             continue;
                         }
@@ -221,11 +348,43 @@ impl SaveFile {
                     let mut buffer = vec![0; metadata.len() as usize];
                     in_file.read(&mut buffer).expect("buffer overflow");
 
-                    let new_player: Player = bincode::deserialize(&buffer)?;
+                    let new_player = Player::deserialize(&buffer)?;
                     self.players.insert(new_player.username.clone(), new_player);
                 }
             }
-            Err(e) => eprintln!("Unable to open player save files with error \"{}\".", e),
+            Err(e) => console_eprintln!("Unable to open player save files with error \"{}\".", e),
+        }
+
+        // Load operators
+        if let Ok(operators) =
+            fs::read_to_string(format!("{}/{}", directory_str, OPERATORS_FILE_NAME))
+        {
+            self.operators = operators
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect();
+        }
+
+        // Load accounts, converting those of older saves
+        let legacy_path = PathBuf::from(format!(
+            "{}/{}.{}",
+            directory_str, LEGACY_ACCOUNTS_FILE_NAME, SAVE_FILE_EXTENSION
+        ));
+        if let Ok(accounts) = fs::read(format!(
+            "{}/{}.{}",
+            directory_str, ACCOUNTS_FILE_NAME, SAVE_FILE_EXTENSION
+        )) {
+            self.accounts = bincode::deserialize(&accounts)?;
+        } else if let Ok(accounts) = fs::read(&legacy_path) {
+            let legacy: HashMap<String, LegacyAccount> = bincode::deserialize(&accounts)?;
+            self.accounts = legacy
+                .into_iter()
+                .map(|(username, account)| (username, account.into()))
+                .collect();
+        }
+        if legacy_path.exists() {
+            self.legacy_accounts = Some(legacy_path);
         }
 
         // Load world
@@ -249,26 +408,16 @@ impl SaveFile {
                 break;
             }
             if buffer[0] == b'C' {
-                let mut buffer: [u8; 12 + 4] = [0; 12 + 4];
+                // Saved before columns had their own files, moved to them by the next save
+                let chunk = read_chunk(&mut reader)?;
+                self.dirty_columns
+                    .insert(Vector2::new(chunk.position.x, chunk.position.z));
+                self.chunk_data.insert(chunk.position, chunk);
+            } else if buffer[0] == b'T' {
+                let mut buffer: [u8; 4] = [0; 4];
                 reader.read_exact(&mut buffer)?;
 
-                let position: Vector3<i32> = bincode::deserialize(&buffer[..12])?;
-                let mut new_chunk = ChunkInfo {
-                    position: position,
-                    data: Vec::new(),
-                };
-
-                let num_sets: u32 = bincode::deserialize(&buffer[12..])?;
-                new_chunk.data.reserve(num_sets as usize);
-
-                for _ in 0..num_sets {
-                    let mut buffer: [u8; 8] = [0; 8];
-                    reader.read_exact(&mut buffer)?;
-
-                    new_chunk.data.push(bincode::deserialize(&buffer)?);
-                }
-
-                self.chunk_data.push(new_chunk);
+                self.world_time = bincode::deserialize(&buffer)?;
             } else if buffer[0] == b'N' {
                 let mut buffer: [u8; 24] = [0; 24];
                 reader.read_exact(&mut buffer)?;
@@ -279,8 +428,69 @@ impl SaveFile {
             }
         }
 
-        println!("Done Reading Save!");
+        // Load columns
+        for entry in fs::read_dir(format!("{}{}", directory_str, COLUMN_SAVE_SUBDIRECTORY))? {
+            let path = entry?.path();
+            if !is_save_file(&path) {
+                continue;
+            }
+            let mut reader = BufReader::new(File::open(&path)?);
+            loop {
+                let mut buffer: [u8; 1] = [0; 1];
+                if reader.read(&mut buffer)? == 0 {
+                    break;
+                }
+                if buffer[0] != b'C' {
+                    bail!("Unknown column data type {} in {:?}", buffer[0], path);
+                }
+                let chunk = read_chunk(&mut reader)?;
+                self.chunk_data.insert(chunk.position, chunk);
+            }
+        }
+
+        console_println!("Done Reading Save!");
 
         Ok(())
     }
 }
+
+/// Whether `path` was written by a save, rather than being left over from one that was cut short
+fn is_save_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == SAVE_FILE_EXTENSION)
+}
+
+fn write_chunk(data: &mut Vec<u8>, chunk: &ChunkInfo) -> Result<()> {
+    data.write_all(&[b'C'])?;
+    // Chunk Position
+    data.write_all(&bincode::serialize(&chunk.position)?)?;
+    data.write_all(&bincode::serialize(&(chunk.data.len() as u32))?)?;
+    for set in &chunk.data {
+        data.write_all(&bincode::serialize(&set)?)?;
+    }
+    Ok(())
+}
+
+/// Reads a chunk written by `write_chunk`, after its leading `C`
+fn read_chunk(reader: &mut impl Read) -> Result<ChunkInfo> {
+    let mut buffer: [u8; 12 + 4] = [0; 12 + 4];
+    reader.read_exact(&mut buffer)?;
+
+    let position: Vector3<i32> = bincode::deserialize(&buffer[..12])?;
+    let mut chunk = ChunkInfo {
+        position,
+        data: Vec::new(),
+    };
+
+    let num_sets: u32 = bincode::deserialize(&buffer[12..])?;
+    chunk.data.reserve(num_sets as usize);
+
+    for _ in 0..num_sets {
+        let mut buffer: [u8; 8] = [0; 8];
+        reader.read_exact(&mut buffer)?;
+
+        chunk.data.push(bincode::deserialize(&buffer)?);
+    }
+
+    Ok(chunk)
+}
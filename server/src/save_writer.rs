@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+
+use crate::console_eprintln;
+
+/// Times a file is tried before it is given up on, one for each save and flush
+const MAX_WRITE_ATTEMPTS: u32 = 5;
+
+/// What the save is asked to do, in order
+enum Message {
+    /// Writes each file
+    Write(Vec<QueuedFile>),
+    /// Replies once everything before it has been tried, with whether it was all written
+    Flush(Sender<bool>),
+}
+
+struct QueuedFile {
+    path: PathBuf,
+    data: Vec<u8>,
+    /// Only written once every file queued before it is written or given up on
+    after_earlier: bool,
+    /// Times writing it has failed
+    failures: u32,
+}
+
+/// Writes save files on a background thread, so saving does not hold up ticks while every file
+/// is synced to disk. Files that cannot be written are retried with the next batch, up to
+/// `MAX_WRITE_ATTEMPTS` times
+pub struct SaveWriter {
+    sender: Option<Sender<Message>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SaveWriter {
+    pub fn new() -> Result<SaveWriter> {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("save writer".to_string())
+            .spawn(move || run(receiver))?;
+        Ok(SaveWriter {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues `files` to be written, each replacing whatever was at its path
+    pub fn write(&self, files: Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+        self.queue(files, false)
+    }
+
+    /// Queues `files` to be written once every file queued before them is written or given up on
+    pub fn write_after(&self, files: Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+        self.queue(files, true)
+    }
+
+    /// Waits for everything queued to be tried, failing if any of it could not be written
+    pub fn flush(&self) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.send(Message::Flush(sender))?;
+        match receiver.recv() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(anyhow!("Some save files could not be written")),
+            Err(_) => Err(anyhow!("The save writer has stopped")),
+        }
+    }
+
+    fn queue(&self, files: Vec<(PathBuf, Vec<u8>)>, after_earlier: bool) -> Result<()> {
+        let files = files
+            .into_iter()
+            .map(|(path, data)| QueuedFile {
+                path,
+                data,
+                after_earlier,
+                failures: 0,
+            })
+            .collect();
+        self.send(Message::Write(files))
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(message).ok())
+            .ok_or_else(|| anyhow!("The save writer has stopped"))
+    }
+}
+
+impl Drop for SaveWriter {
+    /// Finishes writing everything queued before returning
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(receiver: Receiver<Message>) {
+    // Files that could not be written yet, in the order they were queued in
+    let mut pending: Vec<QueuedFile> = Vec::new();
+    // Whether a file was given up on since the last flush
+    let mut dropped = false;
+    for message in receiver {
+        match message {
+            Message::Write(files) => {
+                // Newer contents replace any still waiting for the same file
+                pending.retain(|queued| !files.iter().any(|file| file.path == queued.path));
+                pending.extend(files);
+                dropped |= write_pending(&mut pending);
+            }
+            Message::Flush(reply) => {
+                dropped |= write_pending(&mut pending);
+                let _ = reply.send(pending.is_empty() && !dropped);
+                dropped = false;
+            }
+        }
+    }
+}
+
+/// Tries to write each pending file, keeping those that fail to retry later. Returns whether any
+/// were given up on
+fn write_pending(pending: &mut Vec<QueuedFile>) -> bool {
+    let mut directories = HashSet::new();
+    // Whether a file before the current one is still unwritten
+    let mut blocked = false;
+    let mut dropped = false;
+    pending.retain_mut(|file| {
+        if file.after_earlier && blocked {
+            return true;
+        }
+        if let Err(e) = write_atomically(&file.path, &file.data) {
+            file.failures += 1;
+            if file.failures >= MAX_WRITE_ATTEMPTS {
+                console_eprintln!(
+                    "Unable to write {} after {} attempts, giving up: {}",
+                    file.path.display(),
+                    file.failures,
+                    e
+                );
+                dropped = true;
+                return false;
+            }
+            console_eprintln!("Unable to write {}, will retry: {}", file.path.display(), e);
+            blocked = true;
+            return true;
+        }
+        if let Some(directory) = file.path.parent() {
+            directories.insert(directory.to_path_buf());
+        }
+        false
+    });
+
+    // The renames only last through a crash once the directories holding them are synced
+    for directory in directories {
+        if let Err(e) = sync_directory(&directory) {
+            console_eprintln!("Unable to sync {}: {}", directory.display(), e);
+        }
+    }
+
+    dropped
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it over `path`, so `path` holds
+/// either its old or its new contents even if the server dies part way through
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(temporary, path)
+}
+
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory)?.sync_all()
+}
+
+/// Directories cannot be opened as files everywhere, and do not need syncing there
+#[cfg(not(unix))]
+fn sync_directory(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_failed_writes_are_retried() {
+        let directory = env::temp_dir().join(format!("save_writer_test_{}", std::process::id()));
+        let missing = directory.join("missing");
+        fs::create_dir_all(&directory).unwrap();
+
+        // Other files are written while one that cannot be waits to be retried, except those that
+        // have to come after it
+        let writer = SaveWriter::new().unwrap();
+        writer
+            .write(vec![
+                (missing.join("a"), b"first".to_vec()),
+                (directory.join("b"), b"second".to_vec()),
+            ])
+            .unwrap();
+        writer
+            .write_after(vec![(directory.join("world"), b"world".to_vec())])
+            .unwrap();
+        assert!(writer.flush().is_err());
+        assert_eq!(fs::read(directory.join("b")).unwrap(), b"second");
+        assert!(!directory.join("world").exists());
+
+        fs::create_dir_all(&missing).unwrap();
+        writer
+            .write(vec![(missing.join("a"), b"newer".to_vec())])
+            .unwrap();
+        assert!(writer.flush().is_ok());
+        assert_eq!(fs::read(missing.join("a")).unwrap(), b"newer");
+        assert_eq!(fs::read(directory.join("world")).unwrap(), b"world");
+
+        // A file that never can be written is given up on, letting those after it be written
+        let unwritable = directory.join("never").join("d");
+        writer
+            .write(vec![(unwritable.clone(), b"lost".to_vec())])
+            .unwrap();
+        writer
+            .write_after(vec![(directory.join("world"), b"newer world".to_vec())])
+            .unwrap();
+        let mut flushes = 0;
+        while fs::read(directory.join("world")).unwrap() != b"newer world" {
+            assert!(
+                flushes < MAX_WRITE_ATTEMPTS,
+                "The file was never given up on"
+            );
+            assert!(writer.flush().is_err());
+            flushes += 1;
+        }
+        assert!(!unwritable.exists());
+        assert!(writer.flush().is_ok());
+
+        // Dropping the writer finishes what is queued
+        writer
+            .write(vec![(directory.join("c"), b"last".to_vec())])
+            .unwrap();
+        drop(writer);
+        assert_eq!(fs::read(directory.join("c")).unwrap(), b"last");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
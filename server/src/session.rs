@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
+
+use cgmath::{Vector2, Vector3};
+use common::packets::ChunkRequest;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::PendingAuth;
+use crate::rate_limit::{PeerLimiter, RateLimits};
+use crate::validation::MoveBudget;
+
+/// Identifies a peer for as long as its connection lasts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PeerId(u32);
+
+/// State kept for each connected peer
+pub struct Session {
+    /// Bound once the peer's `PlayerConnect` has been accepted
+    pub username: Option<String>,
+    /// Set while the peer still has to answer an authentication challenge
+    pub pending_auth: Option<PendingAuth>,
+    /// Chunks the peer has been sent, and so needs to hear about changes to, until they leave its
+    /// view distance
+    pub loaded_chunks: HashSet<Vector3<i32>>,
+    /// Whether large payloads sent to the peer are compressed, negotiated during the handshake
+    pub compress: bool,
+    pub limiter: PeerLimiter,
+    /// Chunk requests waiting for the peer to have column generations left
+    pub chunk_requests: VecDeque<ChunkRequest>,
+    /// Column the player was last reported in, streaming is centred on it
+    pub view_center: Option<Vector2<i32>>,
+    /// Columns within the player's view distance, whether or not they have been sent yet
+    pub streamed_columns: HashSet<Vector2<i32>>,
+    /// Streamed columns that still have to be sent, nearest first
+    pub column_queue: VecDeque<Vector2<i32>>,
+    /// Limits how fast the player moves, set once it is accepted
+    pub move_budget: Option<MoveBudget>,
+    /// Set once the peer has been told to disconnect, nothing it sends afterwards is handled
+    pub closing: bool,
+}
+
+/// Every connected peer, keyed by the id handed out when it connected
+#[derive(Default)]
+pub struct Sessions {
+    next_id: u32,
+    sessions: HashMap<PeerId, Session>,
+    limits: RateLimits,
+}
+
+impl Sessions {
+    pub fn new(limits: RateLimits) -> Sessions {
+        Sessions {
+            next_id: 0,
+            sessions: HashMap::new(),
+            limits,
+        }
+    }
+
+    /// Creates a session for a newly connected peer
+    pub fn open(&mut self, now: Instant) -> PeerId {
+        let id = PeerId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.sessions.insert(
+            id,
+            Session {
+                username: None,
+                pending_auth: None,
+                loaded_chunks: HashSet::new(),
+                compress: false,
+                limiter: PeerLimiter::new(&self.limits, now),
+                chunk_requests: VecDeque::new(),
+                view_center: None,
+                streamed_columns: HashSet::new(),
+                column_queue: VecDeque::new(),
+                move_budget: None,
+                closing: false,
+            },
+        );
+
+        id
+    }
+
+    pub fn get_mut(&mut self, id: PeerId) -> Option<&mut Session> {
+        self.sessions.get_mut(&id)
+    }
+
+    /// Removes the session of a disconnected peer
+    pub fn close(&mut self, id: PeerId) -> Option<Session> {
+        self.sessions.remove(&id)
+    }
+
+    /// Attributes every later packet from `id` to `username`
+    pub fn bind(&mut self, id: PeerId, username: &str, compress: bool) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.username = Some(username.to_string());
+            session.compress = compress;
+        }
+    }
+
+    pub fn compresses(&self, id: PeerId) -> bool {
+        self.sessions
+            .get(&id)
+            .is_some_and(|session| session.compress)
+    }
+
+    /// Remembers the challenge sent to `id` until it answers
+    pub fn begin_auth(&mut self, id: PeerId, pending: PendingAuth) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.pending_auth = Some(pending);
+        }
+    }
+
+    /// Removes and returns the challenge `id` is answering, each challenge can only be answered
+    /// once
+    pub fn take_pending_auth(&mut self, id: PeerId) -> Option<PendingAuth> {
+        self.sessions.get_mut(&id)?.pending_auth.take()
+    }
+
+    pub fn load_chunk(&mut self, id: PeerId, chunk_position: Vector3<i32>) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.loaded_chunks.insert(chunk_position);
+        }
+    }
+
+    pub fn has_loaded(&self, id: PeerId, chunk_position: &Vector3<i32>) -> bool {
+        self.sessions
+            .get(&id)
+            .is_some_and(|session| session.loaded_chunks.contains(chunk_position))
+    }
+
+    /// Every peer with an open session, in no particular order
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.sessions.keys().copied().collect()
+    }
+
+    /// Every peer that needs to hear about changes to the chunk at `chunk_position`
+    pub fn peers_with_loaded(&self, chunk_position: &Vector3<i32>) -> Vec<PeerId> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| session.loaded_chunks.contains(chunk_position))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Returns the username bound to `id`, if it has connected as a player
+    pub fn username(&self, id: PeerId) -> Option<&str> {
+        self.sessions.get(&id)?.username.as_deref()
+    }
+
+    /// Returns the peer playing as `username`, if they are online
+    pub fn find_player(&self, username: &str) -> Option<PeerId> {
+        self.sessions
+            .iter()
+            .find(|(_, session)| session.username.as_deref() == Some(username))
+            .map(|(&id, _)| id)
+    }
+
+    pub fn is_online(&self, username: &str) -> bool {
+        self.sessions
+            .values()
+            .any(|session| session.username.as_deref() == Some(username))
+    }
+
+    /// Usernames of every player that has connected, sorted alphabetically
+    pub fn online_players(&self) -> Vec<String> {
+        let mut players: Vec<String> = self
+            .sessions
+            .values()
+            .filter_map(|session| session.username.clone())
+            .collect();
+        players.sort();
+
+        players
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_lifetime() {
+        let mut sessions = Sessions::new(RateLimits::default());
+        let first = sessions.open(Instant::now());
+        let second = sessions.open(Instant::now());
+        assert_ne!(first, second);
+
+        sessions.bind(second, "ethan", true);
+        assert_eq!(sessions.username(first), None);
+        assert_eq!(sessions.username(second), Some("ethan"));
+        assert!(sessions.is_online("ethan"));
+        assert_eq!(sessions.online_players(), vec!["ethan".to_string()]);
+
+        sessions.load_chunk(second, Vector3::new(1, 3, -2));
+        assert!(sessions.has_loaded(second, &Vector3::new(1, 3, -2)));
+        assert!(!sessions.has_loaded(second, &Vector3::new(1, 4, -2)));
+        assert!(!sessions.has_loaded(first, &Vector3::new(1, 3, -2)));
+        assert_eq!(
+            sessions.peers_with_loaded(&Vector3::new(1, 3, -2)),
+            vec![second]
+        );
+        assert!(sessions.compresses(second));
+        assert!(!sessions.compresses(first));
+
+        assert_eq!(
+            sessions.close(second).unwrap().username.as_deref(),
+            Some("ethan")
+        );
+        assert_eq!(sessions.username(second), None);
+        assert!(!sessions.is_online("ethan"));
+    }
+}
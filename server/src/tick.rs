@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many times each second the game is updated
+pub const TICKS_PER_SECOND: u32 = 20;
+/// Time between the starts of two ticks
+pub const TICK_INTERVAL: Duration = Duration::from_millis(1000 / TICKS_PER_SECOND as u64);
+/// Least time between warnings about ticks overrunning, so a slow server does not flood its log
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Ticks run since the server started
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Ticks that took longer than `TICK_INTERVAL`
+static OVERRUNS: AtomicU64 = AtomicU64::new(0);
+/// Total and longest time spent running ticks, in microseconds
+static TOTAL_TICK_MICROS: AtomicU64 = AtomicU64::new(0);
+static LONGEST_TICK_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// Starts ticks every `TICK_INTERVAL`, skipping those the server is too far behind to run
+pub struct TickTimer {
+    /// When the next tick is due
+    next_tick: Instant,
+    /// Ticks that overran since the last warning
+    unreported_overruns: u32,
+    last_warning: Option<Instant>,
+}
+
+impl TickTimer {
+    pub fn new(now: Instant) -> TickTimer {
+        TickTimer {
+            next_tick: now,
+            unreported_overruns: 0,
+            last_warning: None,
+        }
+    }
+
+    /// Sleeps until the next tick is due
+    pub fn wait(&self) {
+        let now = Instant::now();
+        if self.next_tick > now {
+            thread::sleep(self.next_tick - now);
+        }
+    }
+
+    /// Notes that a tick ran from `started` until `finished` and schedules the next one, returning
+    /// a warning if ticks have been overrunning
+    pub fn finish(&mut self, started: Instant, finished: Instant) -> Option<String> {
+        let duration = finished.saturating_duration_since(started);
+        record_tick(duration);
+        if duration > TICK_INTERVAL {
+            self.unreported_overruns += 1;
+        }
+
+        self.next_tick += TICK_INTERVAL;
+        let mut skipped = 0;
+        if finished > self.next_tick {
+            // Catching up by running ticks back to back would only make the server fall further
+            // behind, so the missed ones are dropped
+            let behind = finished - self.next_tick;
+            skipped = (behind.as_micros() / TICK_INTERVAL.as_micros()) as u64;
+            self.next_tick = finished;
+        }
+
+        let warn = self.unreported_overruns > 0
+            && self
+                .last_warning
+                .is_none_or(|last| finished - last >= WARNING_INTERVAL);
+        if !warn {
+            return None;
+        }
+        let warning = format!(
+            "Can't keep up! {} tick(s) took longer than {}ms, the last took {}ms{}",
+            self.unreported_overruns,
+            TICK_INTERVAL.as_millis(),
+            duration.as_millis(),
+            match skipped {
+                0 => String::new(),
+                _ => format!(", skipping {} tick(s)", skipped),
+            }
+        );
+        self.unreported_overruns = 0;
+        self.last_warning = Some(finished);
+        Some(warning)
+    }
+}
+
+fn record_tick(duration: Duration) {
+    let micros = duration.as_micros() as u64;
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_TICK_MICROS.fetch_add(micros, Ordering::Relaxed);
+    LONGEST_TICK_MICROS.fetch_max(micros, Ordering::Relaxed);
+    if duration > TICK_INTERVAL {
+        OVERRUNS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Describes how long ticks have taken so far
+pub fn tick_report() -> String {
+    let ticks = TICKS.load(Ordering::Relaxed);
+    let mean = match ticks {
+        0 => 0.0,
+        _ => TOTAL_TICK_MICROS.load(Ordering::Relaxed) as f64 / ticks as f64 / 1000.0,
+    };
+
+    format!(
+        "Ran {} ticks taking {:.2}ms on average and {:.2}ms at most, {} overran",
+        ticks,
+        mean,
+        LONGEST_TICK_MICROS.load(Ordering::Relaxed) as f64 / 1000.0,
+        OVERRUNS.load(Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_overruns() {
+        let start = Instant::now();
+        let mut timer = TickTimer::new(start);
+
+        // A quick tick leaves the next one due an interval after the first
+        assert_eq!(timer.finish(start, start + Duration::from_millis(5)), None);
+        assert_eq!(timer.next_tick, start + TICK_INTERVAL);
+
+        // A tick lasting three intervals skips the ones it ran into
+        let started = timer.next_tick;
+        let finished = started + TICK_INTERVAL * 3;
+        let warning = timer.finish(started, finished).unwrap();
+        assert!(warning.contains("skipping 2 tick(s)"));
+        assert_eq!(timer.next_tick, finished);
+
+        // Further overruns are only counted until enough time has passed to warn again
+        let started = timer.next_tick;
+        assert_eq!(timer.finish(started, started + TICK_INTERVAL * 2), None);
+        let started = started + WARNING_INTERVAL;
+        let warning = timer.finish(started, started + TICK_INTERVAL * 2).unwrap();
+        assert!(warning.starts_with("Can't keep up! 2 tick(s)"));
+    }
+}
@@ -0,0 +1,229 @@
+use std::time::Instant;
+
+use cgmath::{InnerSpace, Vector3};
+
+/// Seconds of movement at the maximum speed a player may save up by standing still
+const MAX_MOVE_BURST: f32 = 1.0;
+/// Blocks added to every allowance, so rounding and uneven packet timing are not mistaken for
+/// cheating
+const MOVE_TOLERANCE: f32 = 1.0;
+/// How far inside a block the corners checked for line of sight are
+const CORNER_INSET: f32 = 0.05;
+
+/// How far a player may still move, refilled over time at the maximum speed. Spending a budget
+/// rather than checking each move on its own allows for position updates arriving unevenly
+pub struct MoveBudget {
+    /// Blocks each second
+    max_speed: f32,
+    /// Blocks that may be moved right now
+    blocks: f32,
+    refilled: Instant,
+}
+
+impl MoveBudget {
+    /// A full budget for a player moving at up to `max_speed` blocks each second
+    pub fn new(max_speed: f32, now: Instant) -> MoveBudget {
+        MoveBudget {
+            max_speed,
+            blocks: MoveBudget::capacity(max_speed),
+            refilled: now,
+        }
+    }
+
+    fn capacity(max_speed: f32) -> f32 {
+        max_speed * MAX_MOVE_BURST + MOVE_TOLERANCE
+    }
+
+    /// Spends the distance from `from` to `to`, or explains why the move is not allowed
+    pub fn spend(
+        &mut self,
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+        now: Instant,
+    ) -> Result<(), String> {
+        if !(to.x.is_finite() && to.y.is_finite() && to.z.is_finite()) {
+            return Err("moved to an invalid position".to_string());
+        }
+
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f32();
+        self.refilled = now;
+        self.blocks = f32::min(
+            self.blocks + elapsed * self.max_speed,
+            MoveBudget::capacity(self.max_speed),
+        );
+
+        let distance = (to - from).magnitude();
+        if distance > self.blocks {
+            return Err(format!(
+                "moved {:.1} blocks but only {:.1} were allowed",
+                distance, self.blocks
+            ));
+        }
+        self.blocks -= distance;
+        Ok(())
+    }
+}
+
+/// Whether any part of the block at `block` is within `reach` of `eye`
+pub fn within_reach(eye: Vector3<f32>, block: Vector3<i32>, reach: f32) -> bool {
+    let nearest = Vector3::new(
+        eye.x.clamp(block.x as f32, block.x as f32 + 1.0),
+        eye.y.clamp(block.y as f32, block.y as f32 + 1.0),
+        eye.z.clamp(block.z as f32, block.z as f32 + 1.0),
+    );
+    (nearest - eye).magnitude() <= reach
+}
+
+/// Whether the center or a corner of the block at `block` can be seen from `eye` without looking
+/// through a block for which `is_solid` holds
+pub fn has_line_of_sight(
+    eye: Vector3<f32>,
+    block: Vector3<i32>,
+    mut is_solid: impl FnMut(Vector3<i32>) -> bool,
+) -> bool {
+    if !(eye.x.is_finite() && eye.y.is_finite() && eye.z.is_finite()) {
+        return false;
+    }
+    let origin = block.map(|v| v as f32);
+    let mut points = vec![origin + Vector3::new(0.5, 0.5, 0.5)];
+    for corner in 0..8 {
+        let offset = |bit: i32| match corner & bit {
+            0 => CORNER_INSET,
+            _ => 1.0 - CORNER_INSET,
+        };
+        points.push(origin + Vector3::new(offset(1), offset(2), offset(4)));
+    }
+
+    points.into_iter().any(|point| {
+        cells_before(eye, point)
+            .into_iter()
+            .all(|cell| !is_solid(cell))
+    })
+}
+
+/// The blocks passed through going from `from` to `to`, in order, starting with the one `from` is
+/// in and leaving out the one `to` is in
+fn cells_before(from: Vector3<f32>, to: Vector3<f32>) -> Vec<Vector3<i32>> {
+    let target = to.map(|v| v.floor() as i32);
+    let direction = to - from;
+    let mut cell = from.map(|v| v.floor() as i32);
+    let step = direction.map(|v| match v {
+        v if v > 0.0 => 1,
+        v if v < 0.0 => -1,
+        _ => 0,
+    });
+    // Fraction of the way to `to` between crossings of each axis, and to the next crossing
+    let delta = direction.map(|v| 1.0 / v.abs());
+    let mut next = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    for axis in 0..3 {
+        let boundary = match step[axis] {
+            1 => cell[axis] as f32 + 1.0 - from[axis],
+            -1 => from[axis] - cell[axis] as f32,
+            _ => continue,
+        };
+        next[axis] = boundary * delta[axis];
+    }
+
+    let mut cells = Vec::new();
+    while cell != target {
+        cells.push(cell);
+        let axis = match (next.x < next.y, next.x < next.z, next.y < next.z) {
+            (true, true, _) => 0,
+            (false, _, true) => 1,
+            _ => 2,
+        };
+        if next[axis] > 1.0 {
+            break;
+        }
+        cell[axis] += step[axis];
+        next[axis] += delta[axis];
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::MAX_FLY_SPEED;
+    use std::time::Duration;
+
+    #[test]
+    fn test_move_budget() {
+        let start = Instant::now();
+        let origin = Vector3::new(0.0, 80.0, 0.0);
+        let mut budget = MoveBudget::new(10.0, start);
+
+        // Moves may arrive close together as long as they average out
+        let first = Vector3::new(6.0, 80.0, 0.0);
+        assert!(budget.spend(origin, first, start).is_ok());
+        let second = Vector3::new(11.0, 80.0, 0.0);
+        assert!(budget
+            .spend(first, second, start + Duration::from_millis(10))
+            .is_ok());
+        let third = Vector3::new(14.0, 80.0, 0.0);
+        assert!(budget
+            .spend(second, third, start + Duration::from_millis(20))
+            .is_err());
+
+        // Standing still only saves up so much
+        let far = Vector3::new(40.0, 80.0, 0.0);
+        assert!(budget
+            .spend(second, far, start + Duration::from_secs(60))
+            .is_err());
+        let nan = Vector3::new(f32::NAN, 80.0, 0.0);
+        assert!(budget
+            .spend(second, nan, start + Duration::from_secs(61))
+            .is_err());
+    }
+
+    #[test]
+    fn test_move_budget_at_client_speed() {
+        // A client flying diagonally at its top speed, whose position updates arrive unevenly
+        let fly = |speed: f32| {
+            let start = Instant::now();
+            let mut budget = MoveBudget::new(MAX_FLY_SPEED, start);
+            let direction = Vector3::new(1.0, 1.0, 1.0).normalize();
+            let mut previous = Vector3::new(0.0, 80.0, 0.0);
+            (1..40).try_for_each(|update| {
+                let sent = update as f32 * 0.5;
+                let position = Vector3::new(0.0, 80.0, 0.0) + direction * speed * sent;
+                let delay = Duration::from_millis(150 * (update % 2));
+                let arrived = start + Duration::from_secs_f32(sent) + delay;
+                budget.spend(previous, position, arrived)?;
+                previous = position;
+                Ok::<(), String>(())
+            })
+        };
+
+        assert!(fly(MAX_FLY_SPEED).is_ok());
+        assert!(fly(MAX_FLY_SPEED * 1.2).is_err());
+    }
+
+    #[test]
+    fn test_reach() {
+        let eye = Vector3::new(0.5, 81.5, 0.5);
+        assert!(within_reach(eye, Vector3::new(0, 80, 0), 1.0));
+        assert!(within_reach(eye, Vector3::new(8, 81, 0), 8.0));
+        assert!(!within_reach(eye, Vector3::new(9, 81, 0), 8.0));
+        assert!(!within_reach(eye, Vector3::new(7, 81, 7), 8.0));
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let eye = Vector3::new(0.5, 81.5, 0.5);
+        let target = Vector3::new(4, 81, 0);
+        assert!(has_line_of_sight(eye, target, |_| false));
+
+        // A wall between the eye and the block hides it
+        let wall = |cell: Vector3<i32>| cell.x == 2;
+        assert!(!has_line_of_sight(eye, target, wall));
+
+        // The block itself and the ones behind it do not count
+        let behind = |cell: Vector3<i32>| cell.x >= 4;
+        assert!(has_line_of_sight(eye, target, behind));
+
+        // Looking past the edge of a block is enough
+        let pillar = |cell: Vector3<i32>| cell == Vector3::new(2, 81, 0);
+        assert!(has_line_of_sight(eye, Vector3::new(4, 81, 1), pillar));
+    }
+}
@@ -1,15 +1,23 @@
 pub mod chunk_column;
 use cgmath::{Vector2, Vector3};
 pub use chunk_column::ChunkColumn;
+use common::coordinates::{world_to_chunk_position, world_to_position_in_chunk};
 use common::items::ItemManager;
+use common::COLUMN_HEIGHT;
 
 use fast_noise_lite_rs::{FastNoiseLite, NoiseType};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rlua::Lua;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 
 use crate::save_file::SaveFile;
+use crate::{console_eprintln, console_println};
+
+/// Columns further than this from the origin along either axis are never generated
+pub const WORLD_BORDER: i32 = 1 << 20;
 
 #[derive(Serialize, Deserialize)]
 pub struct BlockToPlace {
@@ -25,6 +33,8 @@ pub struct World {
     lua: Lua,
     column_script: String,
     noise_functions: HashMap<String, FastNoiseLite>,
+    /// Chunks generated or changed since they were last saved
+    dirty_chunks: HashSet<Vector3<i32>>,
 }
 
 impl World {
@@ -62,6 +72,7 @@ impl World {
             column_script: fs::read_to_string(column_script_path)
                 .expect("Unable to load generateChunkColumn script"),
             noise_functions,
+            dirty_chunks: HashSet::new(),
         }
     }
 
@@ -73,13 +84,13 @@ impl World {
         let mut was_saved = true;
 
         // For each chunk in column
-        for height in 0..16 as u8 {
+        for height in 0..COLUMN_HEIGHT {
             let saved_chunk =
                 self.save_file
                     .get_chunk(Vector3::<i32>::new(pos.x, height as i32, pos.y));
             match saved_chunk {
                 Some(chunk_data) => {
-                    let chunk = col.get_chunk(height as u8);
+                    let chunk = col.get_chunk(height);
                     let mut i = 0;
                     for set in chunk_data.data.as_slice() {
                         for _ in 0..set.count {
@@ -103,6 +114,8 @@ impl World {
         let set_world_after_list_ptr = &mut set_world_after_list as *mut Vec<ToPlaceAfter>;
 
         if !was_saved {
+            // Seeded so a column is generated the same way every time, which replays rely on
+            let mut rng = StdRng::seed_from_u64(column_seed(self.save_file.world_seed, pos));
             self.lua.context(|lua_ctx| {
                 let globals = lua_ctx.globals(); // Get globals from lua
 
@@ -121,7 +134,7 @@ impl World {
                         .expect("Generate column variables failed to load");
 
                     let rust_random = scope
-                        .create_function(|_, (): ()| Ok(rand::random::<i32>()))
+                        .create_function_mut(|_, (): ()| Ok(rng.gen::<i32>()))
                         .unwrap();
                     globals.set("random", rust_random).unwrap();
 
@@ -190,6 +203,11 @@ impl World {
         }
 
         self.column_map.get_mut(&pos.x).unwrap().insert(pos.y, col);
+        if !was_saved {
+            for height in 0..COLUMN_HEIGHT as i32 {
+                self.dirty_chunks.insert(Vector3::new(pos.x, height, pos.y));
+            }
+        }
 
         for to_place in set_world_after_list.as_slice() {
             self.set_block(&to_place.position, to_place.id);
@@ -202,6 +220,19 @@ impl World {
             && self.column_map.get(&pos.x).unwrap().contains_key(&pos.y)
     }
 
+    /// Returns whether the column at `pos` lies within the world border
+    pub fn is_column_in_bounds(pos: &Vector2<i32>) -> bool {
+        (-WORLD_BORDER..=WORLD_BORDER).contains(&pos.x)
+            && (-WORLD_BORDER..=WORLD_BORDER).contains(&pos.y)
+    }
+
+    /// Returns whether the block at `position` lies within the world border and build height
+    pub fn is_block_in_bounds(position: &Vector3<i32>) -> bool {
+        let chunk_position = world_to_chunk_position(position);
+        (0..COLUMN_HEIGHT as i32).contains(&chunk_position.y)
+            && World::is_column_in_bounds(&Vector2::new(chunk_position.x, chunk_position.z))
+    }
+
     /// Gets the column at `pos` and generates the column if it doesn't exist
     pub fn get_column(&mut self, pos: &Vector2<i32>) -> &mut ChunkColumn {
         if !self.does_column_exist(pos) {
@@ -215,80 +246,62 @@ impl World {
             .unwrap()
     }
 
-    /// Translates absolute world position to absolute column position
-    pub fn world_to_column_position(pos: &Vector2<i32>) -> Vector2<i32> {
-        let mut column_position = Vector2::new(pos.x / 16, pos.y / 16);
-
-        if pos.x < 0 && -pos.x % 16 != 0 {
-            column_position.x -= 1;
-        }
-        if pos.y < 0 && -pos.y % 16 != 0 {
-            column_position.y -= 1;
+    /// Gets the block at `pos`
+    pub fn get_block(&mut self, position: &Vector3<i32>) -> i32 {
+        if !World::is_block_in_bounds(position) {
+            return -1;
         }
 
-        column_position
-    }
-
-    /// Translates absolute world position to absolute chunk position
-    pub fn world_to_chunk_position(pos: &Vector3<i32>) -> Vector3<i32> {
-        let mut chunk_position = Vector3::new(pos.x / 16, pos.y / 16, pos.z / 16);
+        let chunk_position = world_to_chunk_position(position);
+        let block_position_in_chunk = world_to_position_in_chunk(position);
 
-        if pos.x < 0 && -pos.x % 16 != 0 {
-            chunk_position.x -= 1;
-        }
-        if pos.y < 0 && -pos.y % 16 != 0 {
-            chunk_position.y -= 1;
-        }
-        if pos.z < 0 && -pos.z % 16 != 0 {
-            chunk_position.z -= 1;
-        }
+        let column = self.get_column(&Vector2::new(chunk_position.x, chunk_position.z));
 
-        chunk_position
+        column.get_chunk(chunk_position.y as u8).get_block(
+            block_position_in_chunk.x as u8,
+            block_position_in_chunk.y as u8,
+            block_position_in_chunk.z as u8,
+        )
     }
 
-    /// Translates absolute world position into internal chunk position
-    pub fn world_to_position_in_chunk(pos: &Vector3<i32>) -> Vector3<i32> {
-        let mut block_pos_in_chunk = Vector3::new(pos.x % 16, pos.y % 16, pos.z % 16);
-
-        if block_pos_in_chunk.x < 0 {
-            block_pos_in_chunk.x += 16;
-        }
-        if block_pos_in_chunk.y < 0 {
-            block_pos_in_chunk.y += 16;
+    /// Gets the block at `position` without generating its column, `None` if it has not been
+    pub fn get_generated_block(&self, position: &Vector3<i32>) -> Option<i32> {
+        if !World::is_block_in_bounds(position) {
+            return None;
         }
-        if block_pos_in_chunk.z < 0 {
-            block_pos_in_chunk.z += 16;
-        }
-
-        block_pos_in_chunk
-    }
 
-    /// Gets the block at `pos`
-    pub fn get_block(&mut self, position: &Vector3<i32>) -> i32 {
-        let chunk_position = World::world_to_chunk_position(position);
-        let block_position_in_chunk = World::world_to_position_in_chunk(position);
+        let chunk_position = world_to_chunk_position(position);
+        let block_position_in_chunk = world_to_position_in_chunk(position);
+        let column = self
+            .column_map
+            .get(&chunk_position.x)?
+            .get(&chunk_position.z)?;
 
-        let column = self.get_column(&Vector2::new(chunk_position.x, chunk_position.z));
-        if !(chunk_position.y >= 0 && chunk_position.y <= 15) {
-            return -1;
-        }
-
-        column.get_chunk(chunk_position.y as u8).get_block(
+        Some(column.get_chunks()[chunk_position.y as usize].get_block(
             block_position_in_chunk.x as u8,
             block_position_in_chunk.y as u8,
             block_position_in_chunk.z as u8,
-        )
+        ))
     }
 
     /// Sets the block at `pos` to `id`
     pub fn set_block(&mut self, position: &Vector3<i32>, id: i32) {
         if self.item_manager.get_item_by_id(id).is_none() {
-            println!("Tried to set block of unknown id {}", id);
+            console_println!("Tried to set block of unknown id {}", id);
+            return;
+        }
+        if !World::is_block_in_bounds(position) {
+            console_println!(
+                "Tried to set block outside of the world @ {},{},{}",
+                position.x,
+                position.y,
+                position.z
+            );
             return;
         }
 
-        let chunk_position = World::world_to_chunk_position(position);
-        let block_position_in_chunk = World::world_to_position_in_chunk(position);
+        let chunk_position = world_to_chunk_position(position);
+        let block_position_in_chunk = world_to_position_in_chunk(position);
 
         if !self.does_column_exist(&Vector2::new(chunk_position.x, chunk_position.z)) {
             self.generate_column(&Vector2::new(chunk_position.x, chunk_position.z));
@@ -302,146 +315,63 @@ impl World {
                 block_position_in_chunk.z as u8,
                 id,
             );
+        self.dirty_chunks.insert(chunk_position);
+    }
+
+    pub fn get_item_manager(&self) -> &ItemManager {
+        &self.item_manager
     }
 
     pub fn get_save_file(&mut self) -> &mut SaveFile {
         &mut self.save_file
     }
 
+    /// Queues the chunks generated or changed since the last save to be written, along with the
+    /// rest of the save file. `flush_save` waits for them to be written
     pub fn save_to_file(&mut self) {
         if self.save_file.save_directory.is_none() {
+            self.dirty_chunks.clear();
             return;
         }
 
-        println!("Saving world data");
-        for column_x in self.column_map.values() {
-            for column_z in column_x.values() {
-                for chunk in column_z.get_chunks() {
-                    self.save_file.save_chunk_data(chunk);
-                }
+        console_println!("Saving {} changed chunks", self.dirty_chunks.len());
+        for position in self.dirty_chunks.drain() {
+            let column = self
+                .column_map
+                .get(&position.x)
+                .and_then(|column_x| column_x.get(&position.z));
+            if let Some(column) = column {
+                self.save_file
+                    .save_chunk_data(&column.get_chunks()[position.y as usize]);
             }
         }
 
         // TODO: Save block_to_place
 
-        println!("Writing save file");
-        match self.save_file.write_save() {
-            Ok(_) => println!("Save file written"),
-            Err(e) => eprintln!("Save file NOT written with error {}", e),
+        console_println!("Writing save file");
+        if let Err(e) = self.save_file.write_save() {
+            console_eprintln!("Save file NOT written with error {}", e);
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_world_to_column_position() {
-        // Positive
-        for x in 0..48 {
-            for z in 0..48 {
-                assert_eq!(
-                    World::world_to_column_position(&Vector2::new(x, z)),
-                    Vector2::new(x / 16, z / 16)
-                );
+    /// Saves the world and waits for it to be written
+    pub fn flush_save(&mut self) -> Result<(), String> {
+        self.save_to_file();
+        match self.save_file.flush() {
+            Ok(()) => {
+                console_println!("Save file written");
+                Ok(())
             }
-        }
-
-        // Negative
-        for x in -49..0 {
-            for z in -49..0 {
-                let mut correct_x = x / 16;
-                if x < 0 && -x % 16 != 0 {
-                    correct_x -= 1;
-                }
-                let mut correct_z = z / 16;
-                if z < 0 && -z % 16 != 0 {
-                    correct_z -= 1;
-                }
-                assert_eq!(
-                    World::world_to_column_position(&Vector2::new(x, z)),
-                    Vector2::new(correct_x, correct_z)
-                );
+            Err(e) => {
+                console_eprintln!("Save file NOT written with error {}", e);
+                Err(format!("Unable to save the world: {}", e))
             }
         }
     }
+}
 
-    #[test]
-    fn test_world_to_chunk_position() {
-        // Positive
-        for x in 0..48 {
-            for y in 0..48 {
-                for z in 0..48 {
-                    assert_eq!(
-                        World::world_to_chunk_position(&Vector3::new(x, y, z)),
-                        Vector3::new(x / 16, y / 16, z / 16)
-                    );
-                }
-            }
-        }
-
-        // Negative
-        for x in -49..0 {
-            for y in -49..0 {
-                for z in -49..0 {
-                    let mut correct_x = x / 16;
-                    if x < 0 && -x % 16 != 0 {
-                        correct_x -= 1;
-                    }
-                    let mut correct_y = y / 16;
-                    if y < 0 && -y % 16 != 0 {
-                        correct_y -= 1;
-                    }
-                    let mut correct_z = z / 16;
-                    if z < 0 && -z % 16 != 0 {
-                        correct_z -= 1;
-                    }
-                    assert_eq!(
-                        World::world_to_chunk_position(&Vector3::new(x, y, z)),
-                        Vector3::new(correct_x, correct_y, correct_z)
-                    );
-                }
-            }
-        }
-    }
-
-    #[test]
-    fn test_world_to_position_in_chunk() {
-        // Chunk at 0,0,0
-        for x in 0..16 {
-            for y in 0..16 {
-                for z in 0..16 {
-                    assert_eq!(
-                        World::world_to_position_in_chunk(&Vector3::new(x, y, z)),
-                        Vector3::new(x, y, z)
-                    );
-                }
-            }
-        }
-
-        // Chunk at 16,16,16
-        for x in 16..32 {
-            for y in 16..32 {
-                for z in 16..32 {
-                    assert_eq!(
-                        World::world_to_position_in_chunk(&Vector3::new(x, y, z)),
-                        Vector3::new(x - 16, y - 16, z - 16)
-                    );
-                }
-            }
-        }
-
-        // Chunk at -15,-15,-15
-        for x in -16..0 {
-            for y in -16..0 {
-                for z in -16..0 {
-                    assert_eq!(
-                        World::world_to_position_in_chunk(&Vector3::new(x, y, z)),
-                        Vector3::new(x + 16, y + 16, z + 16)
-                    );
-                }
-            }
-        }
-    }
+/// Seeds the random numbers used to generate the column at `pos`, unique to each column of a world
+fn column_seed(world_seed: i32, pos: &Vector2<i32>) -> u64 {
+    let column = (pos.x as u32 as u64) << 32 | pos.y as u32 as u64;
+    (world_seed as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ column
 }
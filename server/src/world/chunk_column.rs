@@ -1,16 +1,16 @@
 use cgmath::{Vector2, Vector3};
-use common::Chunk;
+use common::{Chunk, COLUMN_HEIGHT};
 
 pub struct ChunkColumn {
     chunks: Vec<Chunk>,
 }
 
 impl ChunkColumn {
-    // Creates a new ChunkColumn filled with `id` (16 chunks tall)
+    // Creates a new ChunkColumn filled with `id` (`COLUMN_HEIGHT` chunks tall)
     pub fn new(position: &Vector2<i32>, id: i32) -> ChunkColumn {
         let mut col = ChunkColumn { chunks: Vec::new() };
 
-        for y in 0..16 {
+        for y in 0..COLUMN_HEIGHT as i32 {
             col.chunks
                 .push(Chunk::new(Vector3::new(position.x, y, position.y), id));
         }